@@ -0,0 +1,226 @@
+// Which hosts a source may send requests to.
+//
+// A source is limited to the domains its metadata declares (and their
+// subdomains) plus whatever the host approves on top. Addresses on the local
+// network are refused unless the host allows them explicitly, whether the url
+// names them directly or a domain resolves to them.
+
+use serde_json::Value;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use url::{Host, Url};
+
+// Looks up the addresses of a domain.
+pub trait Resolve: Send + Sync {
+    fn resolve(&self, domain: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+// The system's resolver.
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+    fn resolve(&self, domain: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok((domain, port).to_socket_addrs()?.collect())
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Allowlist {
+    // None when the source declared nothing, which allows any public host
+    domains: Option<Vec<String>>,
+    // approved by the host, checked before anything else
+    extras: Vec<String>,
+    allow_private: bool,
+    // the system's when None
+    resolver: Option<Arc<dyn Resolve>>,
+}
+
+impl fmt::Debug for Allowlist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Allowlist")
+            .field("domains", &self.domains)
+            .field("extras", &self.extras)
+            .field("allow_private", &self.allow_private)
+            .finish()
+    }
+}
+
+impl Allowlist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_domains<S: AsRef<str>>(domains: impl IntoIterator<Item = S>) -> Self {
+        Allowlist {
+            domains: Some(domains.into_iter().filter_map(domain).collect()),
+            ..Self::default()
+        }
+    }
+
+    // Reads the `url` and `urls` of a source.json's `info`.
+    pub fn from_metadata(metadata: &[u8]) -> serde_json::Result<Self> {
+        let metadata: Value = serde_json::from_slice(metadata)?;
+        let info = &metadata["info"];
+        let urls = info["urls"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .chain(Some(&info["url"]))
+            .filter_map(Value::as_str);
+        Ok(Self::from_domains(urls))
+    }
+
+    // Lets the source reach `domain` (a url or bare host) and its subdomains.
+    pub fn allow(mut self, domain: &str) -> Self {
+        self.extras.extend(self::domain(domain));
+        self
+    }
+
+    pub fn allow_private(mut self, allow: bool) -> Self {
+        self.allow_private = allow;
+        self
+    }
+
+    pub fn resolver(mut self, resolver: Arc<dyn Resolve>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    // Why the source may not request `url`, if it may not.
+    pub fn check(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} urls aren't allowed", url.scheme()));
+        }
+        let host = match url.host() {
+            Some(host) => host,
+            None => return Err(String::from("url has no host")),
+        };
+        let name = host.to_string();
+        if self.extras.iter().any(|allowed| matches(&name, allowed)) {
+            return Ok(());
+        }
+        let private = match host {
+            Host::Domain(domain) => {
+                let domain = domain.to_lowercase();
+                domain == "localhost" || domain.ends_with(".localhost")
+            }
+            Host::Ipv4(ip) => is_private(IpAddr::V4(ip)),
+            Host::Ipv6(ip) => is_private(IpAddr::V6(ip)),
+        };
+        if private && !self.allow_private {
+            return Err(format!("{} is a local address", name));
+        }
+        match &self.domains {
+            Some(domains) if !domains.iter().any(|allowed| matches(&name, allowed)) => {
+                Err(format!("{} isn't one of the source's domains", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Checks `url` like `check`, then looks up its domain and refuses it if
+    // any address it resolves to is a local one. The request should connect
+    // only to the returned addresses, so a second lookup can't answer
+    // differently. None when there's nothing to look up: the url names an
+    // address, or the host allowed it to be local. An empty list means the
+    // lookup failed.
+    pub fn resolve(&self, url: &Url) -> Result<Option<Vec<SocketAddr>>, String> {
+        self.check(url)?;
+        let domain = match url.host() {
+            Some(Host::Domain(domain)) => domain,
+            _ => return Ok(None),
+        };
+        if self.allow_private || self.extras.iter().any(|allowed| matches(domain, allowed)) {
+            return Ok(None);
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addresses = match &self.resolver {
+            Some(resolver) => resolver.resolve(domain, port),
+            None => SystemResolver.resolve(domain, port),
+        };
+        let addresses = match addresses {
+            Ok(addresses) => addresses,
+            Err(err) => {
+                log::debug!("failed to look up {}: {}", domain, err);
+                return Ok(Some(Vec::new()));
+            }
+        };
+        match addresses.iter().find(|address| is_private(address.ip())) {
+            Some(address) => Err(format!(
+                "{} resolves to the local address {}",
+                domain,
+                address.ip()
+            )),
+            None => Ok(Some(addresses)),
+        }
+    }
+}
+
+// The host of a url or bare domain, without a leading www.
+fn domain(value: impl AsRef<str>) -> Option<String> {
+    let value = value.as_ref().trim();
+    let host = match Url::parse(value) {
+        Ok(url) => url.host_str()?.to_string(),
+        Err(_) => value.to_string(),
+    };
+    let host = host.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_string())
+    }
+}
+
+fn matches(host: &str, allowed: &str) -> bool {
+    let host = host.to_lowercase();
+    host == allowed
+        || host
+            .strip_suffix(allowed)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => is_private_v6(ip) || embedded_v4(ip).is_some_and(is_private_v4),
+    }
+}
+
+// The IPv4 address an IPv6 one reaches through mapping, the deprecated
+// compatible form, 6to4 or NAT64.
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let octets = ip.octets();
+    let [a, b, c, d] = match ip.segments() {
+        [0, 0, 0, 0, 0, 0 | 0xffff, ..] | [0x64, 0xff9b, 0, 0, 0, 0, ..] => [12, 13, 14, 15],
+        [0x2002, ..] => [2, 3, 4, 5],
+        _ => return None,
+    };
+    Some(Ipv4Addr::new(octets[a], octets[b], octets[c], octets[d]))
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        // "this network", which some systems route to the local host
+        || a == 0
+        // carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // unique local
+        || (first & 0xfe00) == 0xfc00
+        // link-local
+        || (first & 0xffc0) == 0xfe80
+        // site-local, deprecated but still routed locally
+        || (first & 0xffc0) == 0xfec0
+}
@@ -0,0 +1,571 @@
+// On-disk HTTP cache for source requests.
+//
+// The cache wraps another transport. GET responses are stored according to
+// their Cache-Control, Expires, ETag and Last-Modified headers, fresh entries
+// are served without touching the network, and stale ones are revalidated
+// with a conditional request. When the network can't be reached (or the
+// cache is switched to offline mode) stale entries are served as they are,
+// unless they have to be revalidated before every use.
+
+use super::env::{HttpMethod, Request, Response};
+use super::transport::{HttpTransport, NetError, Tee};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// statuses that may be cached without being told to explicitly
+const CACHEABLE_STATUSES: &[i32] = &[200, 203, 204, 300, 301, 308, 404, 410];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Entry {
+    url: String,
+    status: i32,
+    headers: Vec<(String, String)>,
+    final_url: Option<String>,
+    // unix timestamp in seconds
+    stored_at: i64,
+    // request headers named by the response's Vary header
+    vary: Vec<(String, Option<String>)>,
+    // the file in the cache directory holding the body
+    body: String,
+}
+
+impl Entry {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn directives(&self) -> Vec<(String, Option<String>)> {
+        cache_control(self.header("cache-control"))
+    }
+
+    // How long the entry stays fresh after it was stored.
+    fn lifetime(&self, min_ttl: Duration) -> Duration {
+        let directives = self.directives();
+        let max_age = directives
+            .iter()
+            .find(|(name, _)| name == "max-age")
+            .and_then(|(_, value)| value.as_ref()?.parse::<u64>().ok());
+        let lifetime = if directives.iter().any(|(name, _)| name == "no-cache") {
+            Duration::ZERO
+        } else if let Some(max_age) = max_age {
+            Duration::from_secs(max_age)
+        } else if let Some(expires) = self.header("expires") {
+            let expires = httpdate::parse_http_date(expires).unwrap_or(UNIX_EPOCH);
+            let date = self
+                .header("date")
+                .and_then(|date| httpdate::parse_http_date(date).ok())
+                .unwrap_or_else(|| UNIX_EPOCH + Duration::from_secs(self.stored_at as u64));
+            expires.duration_since(date).unwrap_or(Duration::ZERO)
+        } else {
+            Duration::ZERO
+        };
+        lifetime.max(min_ttl)
+    }
+
+    // Includes the time it spent in shared caches before reaching us.
+    fn age(&self) -> Duration {
+        let before = self
+            .header("age")
+            .and_then(|age| age.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        Duration::from_secs(before + (now() - self.stored_at).max(0) as u64)
+    }
+
+    fn is_fresh(&self, min_ttl: Duration) -> bool {
+        self.age() < self.lifetime(min_ttl)
+    }
+
+    // Whether the entry may stand in for a response that couldn't be had.
+    fn may_serve_stale(&self) -> bool {
+        !self.directives().iter().any(|(name, _)| {
+            matches!(
+                name.as_str(),
+                "must-revalidate" | "proxy-revalidate" | "no-cache"
+            )
+        })
+    }
+
+    // Whether the entry may stand in for a server error, which it only does
+    // for as long past its lifetime as its stale-if-error directive says.
+    fn may_serve_on_error(&self, min_ttl: Duration) -> bool {
+        let allowed = self
+            .directives()
+            .into_iter()
+            .find(|(name, _)| name == "stale-if-error")
+            .and_then(|(_, value)| value?.parse::<u64>().ok());
+        match allowed {
+            Some(allowed) => {
+                self.may_serve_stale()
+                    && self.age() < self.lifetime(min_ttl) + Duration::from_secs(allowed)
+            }
+            None => false,
+        }
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request.header(name) == value.as_deref())
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+// Lowercased Cache-Control directives with their values.
+fn cache_control(header: Option<&str>) -> Vec<(String, Option<String>)> {
+    header
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|directive| {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"').to_string())),
+                None => (directive, None),
+            };
+            let name = name.trim().to_lowercase();
+            if name.is_empty() {
+                None
+            } else {
+                Some((name, value))
+            }
+        })
+        .collect()
+}
+
+fn has_directive(header: Option<&str>, directive: &str) -> bool {
+    cache_control(header)
+        .iter()
+        .any(|(name, _)| name == directive)
+}
+
+// FNV-1a, so file names stay the same across builds
+fn key(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+// What the cache can do for a request on its own. Entries come with their
+// body file already open, so a writer replacing it meanwhile doesn't matter.
+enum Lookup {
+    // can be used as it is; the flag says whether it's stale
+    Hit(Entry, File, bool),
+    Fail(anyhow::Error),
+    // needs the network, and isn't cached
+    Bypass,
+    Miss,
+    Stale(Entry, File),
+}
+
+// A body on its way into the cache directory, written to a temporary file
+// that is removed unless it's kept.
+struct Spool {
+    path: PathBuf,
+    // opened on the first write
+    file: Option<BufWriter<File>>,
+}
+
+impl Spool {
+    fn new(dir: &Path) -> Self {
+        Spool {
+            path: dir.join(format!("{:016x}.tmp", fastrand::u64(..))),
+            file: None,
+        }
+    }
+
+    fn file(&mut self) -> io::Result<&mut BufWriter<File>> {
+        if self.file.is_none() {
+            self.file = Some(BufWriter::new(File::create(&self.path)?));
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    // Moves the finished file to `path`.
+    fn keep(mut self, path: &Path) -> io::Result<()> {
+        self.file()?.flush()?;
+        self.file = None;
+        fs::rename(&self.path, path)
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        self.file = None;
+        _ = fs::remove_file(&self.path);
+    }
+}
+
+pub struct HttpCache {
+    dir: PathBuf,
+    inner: Arc<dyn HttpTransport>,
+    // entries count as fresh for at least this long
+    min_ttl: Duration,
+    offline: AtomicBool,
+}
+
+impl HttpCache {
+    pub fn new(dir: impl AsRef<Path>, inner: Arc<dyn HttpTransport>) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(HttpCache {
+            dir: dir.as_ref().to_path_buf(),
+            inner,
+            min_ttl: Duration::ZERO,
+            offline: AtomicBool::new(false),
+        })
+    }
+
+    // Applies even to responses that ask to be revalidated every time.
+    pub fn with_min_ttl(mut self, min_ttl: Duration) -> Self {
+        self.min_ttl = min_ttl;
+        self
+    }
+
+    // Serves everything from the cache, stale or not, without using the network.
+    pub fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::Relaxed);
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline.load(Ordering::Relaxed)
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        fs::remove_dir_all(&self.dir)?;
+        fs::create_dir_all(&self.dir)
+    }
+
+    fn meta_path(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key(url)))
+    }
+
+    fn load(&self, url: &str) -> Option<(Entry, File)> {
+        let entry = self.load_entry(url)?;
+        let file = File::open(self.dir.join(&entry.body)).ok()?;
+        Some((entry, file))
+    }
+
+    fn load_entry(&self, url: &str) -> Option<Entry> {
+        let entry: Entry = serde_json::from_slice(&fs::read(self.meta_path(url)).ok()?).ok()?;
+        // a different url with the same hash
+        if entry.url != url {
+            return None;
+        }
+        Some(entry)
+    }
+
+    // Writes `data` to `path` through a temporary file, so nothing ever sees
+    // it half written.
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let mut spool = Spool::new(&self.dir);
+        spool.write_all(data)?;
+        spool.keep(path)
+    }
+
+    // Each body is stored under a name of its own and only becomes part of
+    // the cache once the metadata naming it replaces the old one, so a crash
+    // or another writer can't pair metadata with the wrong body.
+    fn save(&self, entry: &mut Entry, body: Spool) -> io::Result<()> {
+        entry.body = format!("{}.{:016x}.body", key(&entry.url), fastrand::u64(..));
+        let path = self.dir.join(&entry.body);
+        body.keep(&path)?;
+        let result = self.save_entry(entry);
+        if result.is_err() {
+            _ = fs::remove_file(path);
+        }
+        result
+    }
+
+    // Replaces the metadata, removing the body the old one named if it
+    // changed.
+    fn save_entry(&self, entry: &Entry) -> io::Result<()> {
+        let previous = self.load_entry(&entry.url);
+        self.write_atomic(&self.meta_path(&entry.url), &serde_json::to_vec(entry)?)?;
+        if let Some(previous) = previous.filter(|previous| previous.body != entry.body) {
+            _ = fs::remove_file(self.dir.join(previous.body));
+        }
+        Ok(())
+    }
+
+    fn remove(&self, url: &str) {
+        if let Some(entry) = self.load_entry(url) {
+            _ = fs::remove_file(self.dir.join(entry.body));
+        }
+        _ = fs::remove_file(self.meta_path(url));
+    }
+
+    // Stores a response whose body is either `body`, for downloads, or in the
+    // response itself.
+    fn store(&self, url: &str, request: &Request, response: &Response, body: Option<Spool>) {
+        let cache_control = response.header("cache-control");
+        if has_directive(cache_control.as_deref(), "no-store")
+            || !CACHEABLE_STATUSES.contains(&response.status_code)
+        {
+            self.remove(url);
+            return;
+        }
+        let vary = response.header("vary").unwrap_or_default();
+        if vary.trim() == "*" {
+            self.remove(url);
+            return;
+        }
+        let entry = Entry {
+            url: url.to_string(),
+            status: response.status_code,
+            // cookies belong to the exchange that set them, not to replays
+            headers: response
+                .headers
+                .iter()
+                .filter(|(key, _)| !key.eq_ignore_ascii_case("set-cookie"))
+                .cloned()
+                .collect(),
+            final_url: response.url.clone(),
+            stored_at: now(),
+            vary: vary
+                .split(',')
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    let value = request.header(&name).map(String::from);
+                    (name, value)
+                })
+                .collect(),
+            body: String::new(),
+        };
+        // nothing to gain from entries that are never fresh and can't be revalidated
+        let validators = entry.header("etag").is_some() || entry.header("last-modified").is_some();
+        if entry.lifetime(self.min_ttl).is_zero() && !validators {
+            self.remove(url);
+            return;
+        }
+        let body = match body {
+            Some(body) => Ok(body),
+            None => {
+                let mut body = Spool::new(&self.dir);
+                body.write_all(&response.data).map(|_| body)
+            }
+        };
+        let mut entry = entry;
+        if let Err(err) = body.and_then(|body| self.save(&mut entry, body)) {
+            log::warn!("failed to cache {}: {}", url, err);
+        }
+    }
+
+    // Answers from an entry, streaming its body to `body` for downloads.
+    fn answer(
+        entry: &Entry,
+        mut file: File,
+        stale: bool,
+        body: Option<&mut dyn Write>,
+    ) -> Result<Response> {
+        let mut headers = entry.headers.clone();
+        if stale {
+            headers.push((
+                String::from("warning"),
+                String::from("110 - \"Response is Stale\""),
+            ));
+        }
+        let mut response = Response {
+            status_code: entry.status,
+            headers,
+            url: entry.final_url.clone(),
+            data: Vec::new(),
+            timings: None,
+        };
+        match body {
+            Some(body) if (200..300).contains(&entry.status) => {
+                io::copy(&mut file, body)?;
+            }
+            _ => {
+                file.read_to_end(&mut response.data)?;
+            }
+        }
+        Ok(response)
+    }
+}
+
+impl HttpCache {
+    fn lookup(&self, request: &Request) -> Lookup {
+        let url = request.url.clone().unwrap_or_default();
+        // only plain GETs are cached; requests that manage caching themselves pass through
+        let bypass = request.method != HttpMethod::Get
+            || request.header("if-none-match").is_some()
+            || request.header("if-modified-since").is_some()
+            || has_directive(request.header("cache-control"), "no-store")
+            || has_directive(request.header("cache-control"), "no-cache");
+        let cached = if bypass {
+            None
+        } else {
+            self.load(&url).filter(|(entry, _)| entry.matches(request))
+        };
+        match cached {
+            Some((entry, file)) if entry.is_fresh(self.min_ttl) => Lookup::Hit(entry, file, false),
+            Some((entry, file)) if self.is_offline() && entry.may_serve_stale() => {
+                Lookup::Hit(entry, file, true)
+            }
+            Some(_) if self.is_offline() => {
+                Lookup::Fail(anyhow!("offline and {} must be revalidated", url))
+            }
+            Some((entry, file)) => Lookup::Stale(entry, file),
+            None if self.is_offline() => Lookup::Fail(anyhow!("offline and {} is not cached", url)),
+            None if bypass => Lookup::Bypass,
+            None => Lookup::Miss,
+        }
+    }
+}
+
+impl HttpCache {
+    // Sends through the inner transport. Downloads stream their body to
+    // `tee` instead of the response, which spools a copy for storing.
+    fn send_inner<W: Write>(
+        &self,
+        request: &Request,
+        tee: Option<&mut Tee<W, Spool>>,
+        ready: &dyn Fn(),
+    ) -> Result<Response> {
+        match tee {
+            Some(tee) => self.inner.exchange(request, Some(tee), ready),
+            None => self.inner.exchange(request, None, ready),
+        }
+    }
+
+    fn store_sent<W: Write>(
+        &self,
+        url: &str,
+        request: &Request,
+        response: &Response,
+        tee: Option<Tee<W, Spool>>,
+    ) {
+        match tee {
+            Some(tee) if tee.written() > 0 => match tee.into_copy() {
+                Some(body) => self.store(url, request, response, Some(body)),
+                // the body couldn't be kept, so neither can the old entry
+                None => self.remove(url),
+            },
+            _ => self.store(url, request, response, None),
+        }
+    }
+}
+
+// statuses a stale-if-error entry can stand in for
+const ERROR_STATUSES: &[i32] = &[500, 502, 503, 504];
+
+impl HttpTransport for HttpCache {
+    fn send(&self, request: &Request) -> Result<Response> {
+        self.exchange(request, None, &|| {})
+    }
+
+    fn download(&self, request: &Request, body: &mut dyn Write) -> Result<Response> {
+        self.exchange(request, Some(body), &|| {})
+    }
+
+    fn exchange(
+        &self,
+        request: &Request,
+        mut body: Option<&mut dyn Write>,
+        ready: &dyn Fn(),
+    ) -> Result<Response> {
+        let url = request.url.clone().unwrap_or_default();
+        let (entry, file) = match self.lookup(request) {
+            Lookup::Hit(entry, file, stale) => return Self::answer(&entry, file, stale, body),
+            Lookup::Fail(err) => return Err(err),
+            Lookup::Bypass => return self.inner.exchange(request, body, ready),
+            Lookup::Miss => {
+                let mut tee = body.map(|body| Tee::new(body, Spool::new(&self.dir)));
+                let response = self.send_inner(request, tee.as_mut(), ready)?;
+                self.store_sent(&url, request, &response, tee);
+                return Ok(response);
+            }
+            Lookup::Stale(entry, file) => (entry, file),
+        };
+
+        let mut conditional = request.clone();
+        if let Some(etag) = entry.header("etag") {
+            conditional.set_header("If-None-Match", etag.to_string());
+        }
+        if let Some(last_modified) = entry.header("last-modified") {
+            conditional.set_header("If-Modified-Since", last_modified.to_string());
+        }
+        let mut tee = body
+            .as_deref_mut()
+            .map(|body| Tee::new(body, Spool::new(&self.dir)));
+        let result = self.send_inner(&conditional, tee.as_mut(), ready);
+        match result {
+            Ok(response) if response.status_code == 304 => {
+                drop(tee);
+                // keep the cached body, refreshed with the new headers
+                let mut entry = entry;
+                for (name, value) in &response.headers {
+                    if name.eq_ignore_ascii_case("set-cookie") {
+                        continue;
+                    }
+                    entry
+                        .headers
+                        .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+                    entry.headers.push((name.clone(), value.clone()));
+                }
+                entry.stored_at = now();
+                if let Err(err) = self.save_entry(&entry) {
+                    log::warn!("failed to cache {}: {}", url, err);
+                }
+                let mut cached = Self::answer(&entry, file, false, body)?;
+                // the 304 may set cookies of its own
+                cached.headers.extend(
+                    response
+                        .headers
+                        .into_iter()
+                        .filter(|(key, _)| key.eq_ignore_ascii_case("set-cookie")),
+                );
+                Ok(cached)
+            }
+            Ok(response)
+                if ERROR_STATUSES.contains(&response.status_code)
+                    && entry.may_serve_on_error(self.min_ttl) =>
+            {
+                drop(tee);
+                log::info!("serving stale {} ({})", url, response.status_code);
+                Self::answer(&entry, file, true, body)
+            }
+            Ok(response) => {
+                self.store_sent(&url, request, &response, tee);
+                Ok(response)
+            }
+            Err(err) => {
+                // only when the server couldn't be reached, and not for a
+                // download that failed partway
+                let unreachable = NetError::from_error(&err).kind.is_network();
+                let streamed = tee.is_some_and(|tee| tee.written() > 0);
+                if !unreachable || streamed || !entry.may_serve_stale() {
+                    return Err(err);
+                }
+                log::info!("serving stale {} ({})", url, err);
+                Self::answer(&entry, file, true, body)
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+// Record/replay of HTTP traffic, so source tests can run without a network.
+//
+// In record mode every exchange passes through to a real transport and is
+// written to a JSON file. In replay mode responses are served from that file,
+// and requests that weren't recorded fail instead of reaching the network; a
+// replay that saw any of them panics when it is dropped.
+
+use super::env::{Request, Response};
+use super::transport::{write_body, Capped, HttpTransport, ReqwestTransport, Tee};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Body {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    // used when the body isn't valid UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+    // the full size of a body that was cut short when it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+}
+
+impl Body {
+    fn new(data: &[u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => Body {
+                text: Some(text.to_string()),
+                base64: None,
+                size: None,
+            },
+            Err(_) => Body {
+                text: None,
+                base64: Some(base64::encode(data)),
+                size: None,
+            },
+        }
+    }
+
+    fn capped(copy: &Capped) -> Self {
+        if !copy.is_truncated() {
+            return Body::new(copy.data());
+        }
+        // kept as bytes, since the cut can split a character
+        Body {
+            text: None,
+            base64: Some(base64::encode(copy.data())),
+            size: Some(copy.len()),
+        }
+    }
+
+    fn data(&self) -> Vec<u8> {
+        match (&self.text, &self.base64) {
+            (Some(text), _) => text.as_bytes().to_vec(),
+            (None, Some(encoded)) => base64::decode(encoded).unwrap_or_default(),
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Body>,
+}
+
+impl RecordedRequest {
+    fn new(request: &Request) -> Self {
+        let mut headers: Vec<(String, String)> = request
+            .headers
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.clone()?)))
+            .collect();
+        headers.sort();
+        RecordedRequest {
+            method: request.method.as_str().to_string(),
+            url: request.url.clone().unwrap_or_default(),
+            headers,
+            body: request.body.as_deref().map(Body::new),
+        }
+    }
+
+    // headers aren't compared, since cookies and user agents change between runs
+    fn matches(&self, other: &RecordedRequest) -> bool {
+        self.method == other.method && self.url == other.url && self.body == other.body
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: i32,
+    pub headers: Vec<(String, String)>,
+    pub url: Option<String>,
+    pub body: Body,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+struct State {
+    interactions: Vec<Interaction>,
+    // whether each interaction has been played back yet
+    played: Vec<bool>,
+    unmatched: Vec<String>,
+}
+
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    // the transport being recorded
+    inner: Option<Arc<dyn HttpTransport>>,
+    // downloads longer than this many bytes are cut short when recorded, and
+    // fail when replayed
+    body_limit: Option<usize>,
+    state: Mutex<State>,
+}
+
+impl Cassette {
+    // Records everything sent through `inner` to `path`, replacing what was there.
+    pub fn record(path: impl AsRef<Path>, inner: Arc<dyn HttpTransport>) -> io::Result<Self> {
+        let cassette = Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Record,
+            inner: Some(inner),
+            body_limit: None,
+            state: Mutex::new(State {
+                interactions: Vec::new(),
+                played: Vec::new(),
+                unmatched: Vec::new(),
+            }),
+        };
+        cassette.save(&[])?;
+        Ok(cassette)
+    }
+
+    pub fn replay(path: impl AsRef<Path>) -> io::Result<Self> {
+        let interactions: Vec<Interaction> = serde_json::from_slice(&fs::read(&path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Replay,
+            inner: None,
+            body_limit: None,
+            state: Mutex::new(State {
+                played: vec![false; interactions.len()],
+                interactions,
+                unmatched: Vec::new(),
+            }),
+        })
+    }
+
+    // Replays `path`, or records over the network when the `AIDOKU_CASSETTE`
+    // environment variable asks for it. `record` writes next to `path`, as
+    // `<name>.recorded.json`, so a committed cassette only changes once its
+    // recording is moved over it; `overwrite` records to `path` itself.
+    pub fn from_env(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match std::env::var("AIDOKU_CASSETTE").as_deref() {
+            Ok("record") => {
+                let recording = path.with_extension("recorded.json");
+                log::info!("recording {} to {}", path.display(), recording.display());
+                Self::record(recording, Arc::new(ReqwestTransport::new()))
+            }
+            Ok("overwrite") => Self::record(path, Arc::new(ReqwestTransport::new())),
+            _ => Self::replay(path),
+        }
+    }
+
+    pub fn with_body_limit(mut self, limit: Option<usize>) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.state.lock().unwrap().interactions.clone()
+    }
+
+    // Requests that had no recorded response during replay.
+    pub fn unmatched(&self) -> Vec<String> {
+        self.state.lock().unwrap().unmatched.clone()
+    }
+
+    // Like `unmatched`, but they no longer count against the cassette when
+    // it is dropped.
+    pub fn take_unmatched(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.lock().unwrap().unmatched)
+    }
+
+    fn record_response(
+        &self,
+        request: RecordedRequest,
+        response: &Response,
+        body: Body,
+    ) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.interactions.push(Interaction {
+            request,
+            response: RecordedResponse {
+                status: response.status_code,
+                headers: response.headers.clone(),
+                url: response.url.clone(),
+                body,
+            },
+        });
+        state.played.push(true);
+        self.save(&state.interactions)
+    }
+
+    fn save(&self, interactions: &[Interaction]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_vec_pretty(interactions)?)
+    }
+}
+
+impl Cassette {
+    fn replay_response(&self, request: &Request) -> Result<Response> {
+        let recorded = RecordedRequest::new(request);
+        let mut state = self.state.lock().unwrap();
+        let State {
+            interactions,
+            played,
+            unmatched,
+        } = &mut *state;
+        // identical requests are answered in the order they were recorded
+        let index = interactions
+            .iter()
+            .zip(played.iter())
+            .position(|(interaction, played)| !played && interaction.request.matches(&recorded));
+        match index {
+            Some(index) => {
+                played[index] = true;
+                let response = &interactions[index].response;
+                if let Some(size) = response.body.size {
+                    return Err(anyhow!(
+                        "the recorded body of {} {} was cut short of its {} bytes",
+                        recorded.method,
+                        recorded.url,
+                        size
+                    ));
+                }
+                Ok(Response {
+                    status_code: response.status,
+                    headers: response.headers.clone(),
+                    url: response.url.clone(),
+                    data: response.body.data(),
+                    timings: None,
+                })
+            }
+            None => {
+                let request = format!("{} {}", recorded.method, recorded.url);
+                log::warn!(
+                    "cassette {}: no recorded response for {}",
+                    self.path.display(),
+                    request
+                );
+                unmatched.push(request.clone());
+                Err(anyhow!("no recorded response for {}", request))
+            }
+        }
+    }
+}
+
+impl HttpTransport for Cassette {
+    fn send(&self, request: &Request) -> Result<Response> {
+        self.exchange(request, None, &|| {})
+    }
+
+    fn download(&self, request: &Request, body: &mut dyn Write) -> Result<Response> {
+        self.exchange(request, Some(body), &|| {})
+    }
+
+    fn exchange(
+        &self,
+        request: &Request,
+        body: Option<&mut dyn Write>,
+        ready: &dyn Fn(),
+    ) -> Result<Response> {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            // replays never reach the network
+            None => {
+                let response = self.replay_response(request);
+                return match body {
+                    Some(body) => write_body(response?, body),
+                    None => response,
+                };
+            }
+        };
+        let recorded = RecordedRequest::new(request);
+        let body = match body {
+            Some(body) => body,
+            None => {
+                let response = inner.exchange(request, None, ready)?;
+                self.record_response(recorded, &response, Body::new(&response.data))?;
+                return Ok(response);
+            }
+        };
+        // the body is recorded as it streams through, up to the limit
+        let mut tee = Tee::new(body, Capped::new(self.body_limit));
+        let response = inner.exchange(request, Some(&mut tee), ready)?;
+        let recorded_body = match tee.into_copy() {
+            Some(copy) if !copy.is_empty() => Body::capped(&copy),
+            _ => Body::new(&response.data),
+        };
+        self.record_response(recorded, &response, recorded_body)?;
+        Ok(response)
+    }
+}
+
+// A request missing from a cassette means it is out of date, which a test
+// shouldn't pass with just because the source coped with the failure.
+impl Drop for Cassette {
+    fn drop(&mut self) {
+        let unmatched = match self.state.get_mut() {
+            Ok(state) => &state.unmatched,
+            Err(_) => return,
+        };
+        if !unmatched.is_empty() && !std::thread::panicking() {
+            panic!(
+                "cassette {}: no recorded response for {}",
+                self.path.display(),
+                unmatched.join(", ")
+            );
+        }
+    }
+}
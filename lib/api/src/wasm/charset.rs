@@ -0,0 +1,171 @@
+// Turns response bytes into text.
+//
+// The encoding comes from, in order: a byte order mark, the Content-Type
+// charset, a charset declared in the document itself, and finally a guess
+// when the bytes aren't valid UTF-8.
+
+use encoding_rs::{Encoding, BIG5, EUC_JP, EUC_KR, GBK, SHIFT_JIS, UTF_8, WINDOWS_1252};
+
+// how far into a document to look for a <meta charset>, as browsers do
+const PRESCAN_LENGTH: usize = 1024;
+
+// Tried when nothing declares an encoding and the bytes aren't UTF-8. These
+// accept much of each other's text, so every one that decodes without errors
+// is scored, and ties go to the earlier one. Chinese and Korean text reads as
+// kanji in EUC-JP, so it only wins on kana.
+const FALLBACKS: &[&Encoding] = &[EUC_KR, GBK, SHIFT_JIS, EUC_JP, BIG5];
+
+pub fn decode(data: &[u8], content_type: Option<&str>) -> String {
+    let encoding = detect(data, content_type);
+    let (text, _, _) = encoding.decode(data);
+    text.into_owned()
+}
+
+pub fn detect(data: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(data) {
+        return encoding;
+    }
+    if let Some(encoding) = content_type
+        .and_then(charset_param)
+        .and_then(|name| Encoding::for_label(name.as_bytes()))
+    {
+        return encoding;
+    }
+    let markup = data
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_some_and(|byte| *byte == b'<');
+    if let Some(encoding) = declared_charset(data)
+        .filter(|_| markup)
+        .and_then(declared_label)
+    {
+        return encoding;
+    }
+    if std::str::from_utf8(data).is_ok() {
+        return UTF_8;
+    }
+    let mut best: Option<(&'static Encoding, f64)> = None;
+    for encoding in FALLBACKS.iter().copied() {
+        if encoding
+            .decode_without_bom_handling_and_without_replacement(data)
+            .is_none()
+        {
+            continue;
+        }
+        let score = score(encoding, data);
+        if !matches!(best, Some((_, best)) if best >= score) {
+            best = Some((encoding, score));
+        }
+    }
+    best.map_or(WINDOWS_1252, |(encoding, _)| encoding)
+}
+
+// How much of the text falls among the characters its language uses most,
+// from 0 to 1. Each encoding lays those out in a block of their own.
+fn score(encoding: &'static Encoding, data: &[u8]) -> f64 {
+    let (mut total, mut counted) = (0.0, 0);
+    let mut bytes = data.iter().copied();
+    while let Some(lead) = bytes.next() {
+        let weight = if lead < 0x80 {
+            None
+        } else if encoding == SHIFT_JIS && (0xa1..=0xdf).contains(&lead) {
+            // half-width katakana
+            Some(0.0)
+        } else if encoding == EUC_JP && lead == 0x8f {
+            // the supplementary kanji take three bytes
+            bytes.next();
+            bytes.next();
+            Some(0.0)
+        } else {
+            weight(encoding, lead, bytes.next().unwrap_or(0))
+        };
+        if let Some(weight) = weight {
+            total += weight;
+            counted += 1;
+        }
+    }
+    if counted == 0 {
+        0.0
+    } else {
+        total / counted as f64
+    }
+}
+
+// How typical the two byte character `lead`, `trail` is of text in
+// `encoding`. Punctuation and symbols say nothing either way.
+fn weight(encoding: &'static Encoding, lead: u8, trail: u8) -> Option<f64> {
+    let pair = u16::from_be_bytes([lead, trail]);
+    if encoding == SHIFT_JIS {
+        match pair {
+            0x8140..=0x829e => None,
+            // hiragana and katakana
+            0x829f..=0x8396 => Some(1.0),
+            // the first level of kanji, ordered by frequency
+            0x889f..=0x9872 => Some(1.0),
+            _ => Some(0.0),
+        }
+    } else if encoding == EUC_JP {
+        match pair {
+            0xa1a1..=0xa3fe => None,
+            0xa4a1..=0xa5fe => Some(1.0),
+            0xb0a1..=0xcfd3 => Some(1.0),
+            _ => Some(0.0),
+        }
+    } else {
+        // the first level of hanzi in GBK, hangul in EUC-KR, and the
+        // frequent hanzi in Big5
+        let common = if encoding == GBK {
+            0xb0a1..=0xd7f9
+        } else if encoding == EUC_KR {
+            0xb0a1..=0xc8fe
+        } else {
+            0xa440..=0xc67e
+        };
+        match pair {
+            0xa140..=0xa3fe if encoding == BIG5 || trail >= 0xa1 => None,
+            _ if common.contains(&pair) && (encoding == BIG5 || trail >= 0xa1) => Some(1.0),
+            _ => Some(0.0),
+        }
+    }
+}
+
+fn declared_label(name: &str) -> Option<&'static Encoding> {
+    let encoding = Encoding::for_label(name.trim().as_bytes())?;
+    // a document can't really be UTF-16 if its declaration could be read as ASCII
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        Some(UTF_8)
+    } else {
+        Some(encoding)
+    }
+}
+
+// The charset parameter of a Content-Type header.
+fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches(['"', '\'']))
+        } else {
+            None
+        }
+    })
+}
+
+// A charset from a <meta charset>, a <meta http-equiv> or an XML declaration
+// near the start of the document.
+fn declared_charset(data: &[u8]) -> Option<&str> {
+    let head = &data[..data.len().min(PRESCAN_LENGTH)];
+    let head = std::str::from_utf8(head)
+        .or_else(|err| std::str::from_utf8(&head[..err.valid_up_to()]))
+        .ok()?;
+    let lower = head.to_ascii_lowercase();
+    let keys = ["charset=", "encoding="];
+    keys.iter().find_map(|key| {
+        let start = lower.find(key)? + key.len();
+        let value = head[start..].trim_start_matches(['"', '\'']);
+        let end = value
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')))
+            .unwrap_or(value.len());
+        Some(&value[..end]).filter(|value| !value.is_empty())
+    })
+}
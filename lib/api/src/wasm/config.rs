@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+// what the iOS app sends, so sources see the same pages here
+pub const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 16_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Mobile/15E148 Safari/604.1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    // redirect responses are handed to the source as they are
+    None,
+    // follow at most this many redirects
+    Limited(usize),
+}
+
+// When and how often failed requests are sent again.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // including the first one, so 1 disables retries
+    pub max_attempts: u32,
+    // doubled after each failed attempt
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // a server asking for a longer wait than this gets its response back
+    // instead of a retry
+    pub max_retry_after: Duration,
+    pub statuses: Vec<i32>,
+    // retry POST as well, which may repeat its side effects
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_retry_after: Duration::from_secs(120),
+            statuses: vec![408, 429, 500, 502, 503, 504],
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // How long to wait before the attempt after `attempt` (counting from 1),
+    // or None to stop retrying. A server's Retry-After wins over the backoff,
+    // since asking again any sooner would only be refused.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return Some(retry_after).filter(|delay| *delay <= self.max_retry_after);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        // somewhere in the upper half, so instances don't retry in lockstep
+        Some(backoff.mul_f64(0.5 + fastrand::f64() / 2.0))
+    }
+}
+
+// How a source's requests reach the network. Each env builds one client from
+// this and reuses it, so connections are pooled across requests.
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    // used when the source doesn't set its own
+    pub user_agent: String,
+    // http://, https://, socks5:// or socks5h:// url
+    pub proxy: Option<String>,
+    // for the whole request, including reading the body
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    // PEM files trusted in addition to the system roots
+    pub ca_certificates: Vec<PathBuf>,
+    pub redirects: RedirectPolicy,
+    // how long idle pooled connections are kept open, and the TCP keep-alive
+    // interval; no HTTP/2 pings are sent, as the blocking client can't
+    pub keep_alive: Option<Duration>,
+    pub retry: RetryPolicy,
+    // larger response bodies fail with `NetErrorKind::TooLarge`
+    pub max_body_size: Option<usize>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            user_agent: String::from(DEFAULT_USER_AGENT),
+            proxy: None,
+            timeout: Some(Duration::from_secs(60)),
+            connect_timeout: Some(Duration::from_secs(15)),
+            ca_certificates: Vec::new(),
+            redirects: RedirectPolicy::Limited(10),
+            keep_alive: Some(Duration::from_secs(90)),
+            retry: RetryPolicy::default(),
+            max_body_size: Some(100 * 1024 * 1024),
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn max_redirects(&self) -> usize {
+        match self.redirects {
+            RedirectPolicy::None => 0,
+            RedirectPolicy::Limited(max) => max,
+        }
+    }
+}
@@ -0,0 +1,334 @@
+// Cookie storage for sources.
+//
+// Every instance of a source shares one jar. Cookies from `Set-Cookie`
+// response headers are stored and sent back on matching requests, the host
+// can inspect and edit them, and a jar can be backed by a JSON file so it
+// survives between runs.
+
+use publicsuffix::{List, Psl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    // only sent to `domain` itself, not its subdomains
+    pub host_only: bool,
+    pub path: String,
+    // unix timestamp in seconds; session cookies have none
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    // Parses a `Set-Cookie` header value received from `url`.
+    pub fn parse(header: &str, url: &Url) -> Option<Cookie> {
+        let host = url.host_str()?.to_lowercase();
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            expires: None,
+            secure: false,
+            http_only: false,
+        };
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_lowercase().as_str() {
+                "expires" => cookie.expires = parse_expires(value).or(cookie.expires),
+                "max-age" => max_age = value.parse::<i64>().ok().or(max_age),
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_lowercase();
+                    if !domain_matches(&host, &domain) {
+                        return None;
+                    }
+                    // a public suffix is only allowed as the exact host, and
+                    // then the cookie stays with that host
+                    if is_public_suffix(&domain) {
+                        if domain != host {
+                            return None;
+                        }
+                        continue;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+        // only a secure connection can set a cookie that is kept to them
+        if cookie.secure && url.scheme() != "https" {
+            return None;
+        }
+        // max-age wins over expires
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(if max_age > 0 { now() + max_age } else { 0 });
+        }
+        Some(cookie)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now())
+    }
+
+    pub fn matches(&self, url: &Url) -> bool {
+        let host = match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => return false,
+        };
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+fn parse_expires(value: &str) -> Option<i64> {
+    // cookies commonly use dashes ("Wed, 21-Oct-2015 07:28:00 GMT")
+    let time = httpdate::parse_http_date(value)
+        .or_else(|_| httpdate::parse_http_date(&value.replace('-', " ")))
+        .ok()?;
+    Some(match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(_) => 0,
+    })
+}
+
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => String::from("/"),
+        Some(index) => url.path()[..index].to_string(),
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<IpAddr>().is_err())
+}
+
+// Whether cookies for `domain` would be shared between unrelated sites, like
+// `com` or `co.uk`. The list is vendored; data/README.md says how to update it.
+fn is_public_suffix(domain: &str) -> bool {
+    static LIST: OnceLock<List> = OnceLock::new();
+    let list = LIST.get_or_init(|| {
+        include_str!("../../data/public_suffix_list.dat")
+            .parse()
+            .expect("bundled public suffix list should parse")
+    });
+    list.domain(domain.as_bytes()).is_none()
+}
+
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+#[derive(Debug, Default)]
+struct State {
+    cookies: Vec<Cookie>,
+    file: Option<PathBuf>,
+    // bumped on every change, so an older snapshot never overwrites a newer one
+    version: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    state: Mutex<State>,
+    // the version last written to the file; held while writing
+    saved: Mutex<u64>,
+}
+
+// The jar's contents at one point, to be written without holding its lock.
+struct Snapshot {
+    file: PathBuf,
+    cookies: Vec<Cookie>,
+    version: u64,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Loads cookies saved at `path`, if any, and saves the jar there after
+    // every change from now on.
+    pub fn persist_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let saved: Vec<Cookie> = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            for cookie in saved {
+                insert(&mut state.cookies, cookie);
+            }
+            state.file = Some(path);
+            snapshot(&mut state)
+        };
+        self.save(snapshot)
+    }
+
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let state = self.state.lock().unwrap();
+        state
+            .cookies
+            .iter()
+            .filter(|cookie| !cookie.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    // Adds or replaces a cookie. Expired cookies remove the one they replace.
+    pub fn set_cookie(&self, cookie: Cookie) {
+        self.update(|cookies| insert(cookies, cookie));
+    }
+
+    // Stores the cookies from the `Set-Cookie` headers of a response to `url`.
+    pub fn set_cookies<'a>(&self, url: &Url, headers: impl IntoIterator<Item = &'a str>) {
+        let cookies: Vec<Cookie> = headers
+            .into_iter()
+            .filter_map(|header| Cookie::parse(header, url))
+            .collect();
+        if !cookies.is_empty() {
+            self.update(|jar| {
+                for cookie in cookies {
+                    insert(jar, cookie);
+                }
+            });
+        }
+    }
+
+    pub fn remove(&self, domain: &str, name: &str) {
+        self.update(|cookies| {
+            cookies.retain(|cookie| !(cookie.domain == domain && cookie.name == name))
+        });
+    }
+
+    pub fn clear(&self) {
+        self.update(|cookies| cookies.clear());
+    }
+
+    // The `Cookie` header to send with a request to `url`.
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let mut cookies: Vec<&Cookie> = state
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        // more specific paths first
+        cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        Some(
+            cookies
+                .iter()
+                .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Vec<Cookie>)) {
+        let snapshot = {
+            let mut state = self.state.lock().unwrap();
+            f(&mut state.cookies);
+            state.cookies.retain(|cookie| !cookie.is_expired());
+            snapshot(&mut state)
+        };
+        if let Err(err) = self.save(snapshot) {
+            log::warn!("failed to save cookies: {}", err);
+        }
+    }
+
+    fn save(&self, snapshot: Option<Snapshot>) -> io::Result<()> {
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None => return Ok(()),
+        };
+        let mut saved = self.saved.lock().unwrap();
+        if *saved >= snapshot.version {
+            return Ok(());
+        }
+        fs::write(
+            &snapshot.file,
+            serde_json::to_vec_pretty(&snapshot.cookies)?,
+        )?;
+        *saved = snapshot.version;
+        Ok(())
+    }
+}
+
+fn snapshot(state: &mut State) -> Option<Snapshot> {
+    state.version += 1;
+    Some(Snapshot {
+        file: state.file.clone()?,
+        cookies: state.cookies.clone(),
+        version: state.version,
+    })
+}
+
+fn insert(cookies: &mut Vec<Cookie>, cookie: Cookie) {
+    cookies.retain(|existing| {
+        !(existing.name == cookie.name
+            && existing.domain == cookie.domain
+            && existing.path == cookie.path)
+    });
+    if !cookie.is_expired() {
+        cookies.push(cookie);
+    }
+}
+
+// Returns the jar shared by every instance of the source with `id`.
+pub fn shared(id: &str) -> Arc<CookieJar> {
+    static JARS: OnceLock<Mutex<HashMap<String, Arc<CookieJar>>>> = OnceLock::new();
+    JARS.get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(id.to_string())
+        .or_default()
+        .clone()
+}
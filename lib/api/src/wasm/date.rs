@@ -0,0 +1,851 @@
+// Parser for the Unicode (LDML) date patterns sources pass to `read_date_string`,
+// e.g. "MMM d, yyyy 'at' h:mm a". Text fields are matched against localized
+// names with English as a fallback, and times without an explicit offset are
+// interpreted in the requested timezone.
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Field(char, usize),
+    Literal(String),
+}
+
+fn tokenize(pattern: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    let mut literal = String::new();
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // '' is an escaped quote, otherwise everything up to the next quote is literal
+            if chars.peek() == Some(&'\'') {
+                chars.next();
+                literal.push('\'');
+                continue;
+            }
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                        literal.push('\'');
+                    } else {
+                        break;
+                    }
+                } else {
+                    literal.push(c);
+                }
+            }
+        } else if c.is_ascii_alphabetic() {
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            let mut count = 1;
+            while chars.peek() == Some(&c) {
+                chars.next();
+                count += 1;
+            }
+            tokens.push(Token::Field(c, count));
+        } else {
+            literal.push(c);
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    tokens
+}
+
+fn is_numeric(token: Option<&Token>) -> bool {
+    match token {
+        Some(Token::Field(c, count)) => match c {
+            'M' | 'L' => *count <= 2,
+            'y' | 'u' | 'd' | 'D' | 'H' | 'h' | 'K' | 'k' | 'm' | 's' | 'S' => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+struct Names {
+    months: [&'static [&'static str]; 12],
+    weekdays: [&'static [&'static str]; 7],
+    periods: [&'static [&'static str]; 2],
+}
+
+const EN: Names = Names {
+    months: [
+        &["January", "Jan"],
+        &["February", "Feb"],
+        &["March", "Mar"],
+        &["April", "Apr"],
+        &["May"],
+        &["June", "Jun"],
+        &["July", "Jul"],
+        &["August", "Aug"],
+        &["September", "Sept", "Sep"],
+        &["October", "Oct"],
+        &["November", "Nov"],
+        &["December", "Dec"],
+    ],
+    weekdays: [
+        &["Sunday", "Sun"],
+        &["Monday", "Mon"],
+        &["Tuesday", "Tues", "Tue"],
+        &["Wednesday", "Wed"],
+        &["Thursday", "Thurs", "Thu"],
+        &["Friday", "Fri"],
+        &["Saturday", "Sat"],
+    ],
+    periods: [&["AM", "a.m."], &["PM", "p.m."]],
+};
+
+const ES: Names = Names {
+    months: [
+        &["enero", "ene"],
+        &["febrero", "feb"],
+        &["marzo", "mar"],
+        &["abril", "abr"],
+        &["mayo", "may"],
+        &["junio", "jun"],
+        &["julio", "jul"],
+        &["agosto", "ago"],
+        &["septiembre", "setiembre", "sept", "sep", "set"],
+        &["octubre", "oct"],
+        &["noviembre", "nov"],
+        &["diciembre", "dic"],
+    ],
+    weekdays: [
+        &["domingo", "dom"],
+        &["lunes", "lun"],
+        &["martes", "mar"],
+        &["miércoles", "mié"],
+        &["jueves", "jue"],
+        &["viernes", "vie"],
+        &["sábado", "sáb"],
+    ],
+    periods: [&["a. m.", "a.m."], &["p. m.", "p.m."]],
+};
+
+const PT: Names = Names {
+    months: [
+        &["janeiro", "jan"],
+        &["fevereiro", "fev"],
+        &["março", "mar"],
+        &["abril", "abr"],
+        &["maio", "mai"],
+        &["junho", "jun"],
+        &["julho", "jul"],
+        &["agosto", "ago"],
+        &["setembro", "set"],
+        &["outubro", "out"],
+        &["novembro", "nov"],
+        &["dezembro", "dez"],
+    ],
+    weekdays: [
+        &["domingo", "dom"],
+        &["segunda-feira", "segunda", "seg"],
+        &["terça-feira", "terça", "ter"],
+        &["quarta-feira", "quarta", "qua"],
+        &["quinta-feira", "quinta", "qui"],
+        &["sexta-feira", "sexta", "sex"],
+        &["sábado", "sáb"],
+    ],
+    periods: [&[], &[]],
+};
+
+const FR: Names = Names {
+    months: [
+        &["janvier", "janv"],
+        &["février", "févr"],
+        &["mars"],
+        &["avril", "avr"],
+        &["mai"],
+        &["juin"],
+        &["juillet", "juil"],
+        &["août"],
+        &["septembre", "sept"],
+        &["octobre", "oct"],
+        &["novembre", "nov"],
+        &["décembre", "déc"],
+    ],
+    weekdays: [
+        &["dimanche", "dim"],
+        &["lundi", "lun"],
+        &["mardi", "mar"],
+        &["mercredi", "mer"],
+        &["jeudi", "jeu"],
+        &["vendredi", "ven"],
+        &["samedi", "sam"],
+    ],
+    periods: [&[], &[]],
+};
+
+const DE: Names = Names {
+    months: [
+        &["Januar", "Jänner", "Jan"],
+        &["Februar", "Feb"],
+        &["März", "Mär", "Mrz"],
+        &["April", "Apr"],
+        &["Mai"],
+        &["Juni", "Jun"],
+        &["Juli", "Jul"],
+        &["August", "Aug"],
+        &["September", "Sept", "Sep"],
+        &["Oktober", "Okt"],
+        &["November", "Nov"],
+        &["Dezember", "Dez"],
+    ],
+    weekdays: [
+        &["Sonntag", "So"],
+        &["Montag", "Mo"],
+        &["Dienstag", "Di"],
+        &["Mittwoch", "Mi"],
+        &["Donnerstag", "Do"],
+        &["Freitag", "Fr"],
+        &["Samstag", "Sonnabend", "Sa"],
+    ],
+    periods: [&[], &[]],
+};
+
+const IT: Names = Names {
+    months: [
+        &["gennaio", "gen"],
+        &["febbraio", "feb"],
+        &["marzo", "mar"],
+        &["aprile", "apr"],
+        &["maggio", "mag"],
+        &["giugno", "giu"],
+        &["luglio", "lug"],
+        &["agosto", "ago"],
+        &["settembre", "set"],
+        &["ottobre", "ott"],
+        &["novembre", "nov"],
+        &["dicembre", "dic"],
+    ],
+    weekdays: [
+        &["domenica", "dom"],
+        &["lunedì", "lun"],
+        &["martedì", "mar"],
+        &["mercoledì", "mer"],
+        &["giovedì", "gio"],
+        &["venerdì", "ven"],
+        &["sabato", "sab"],
+    ],
+    periods: [&[], &[]],
+};
+
+const RU: Names = Names {
+    months: [
+        &["января", "январь", "янв"],
+        &["февраля", "февраль", "фев"],
+        &["марта", "март", "мар"],
+        &["апреля", "апрель", "апр"],
+        &["мая", "май"],
+        &["июня", "июнь", "июн"],
+        &["июля", "июль", "июл"],
+        &["августа", "август", "авг"],
+        &["сентября", "сентябрь", "сент", "сен"],
+        &["октября", "октябрь", "окт"],
+        &["ноября", "ноябрь", "ноя"],
+        &["декабря", "декабрь", "дек"],
+    ],
+    weekdays: [
+        &["воскресенье", "вс"],
+        &["понедельник", "пн"],
+        &["вторник", "вт"],
+        &["среда", "ср"],
+        &["четверг", "чт"],
+        &["пятница", "пт"],
+        &["суббота", "сб"],
+    ],
+    periods: [&[], &[]],
+};
+
+const PL: Names = Names {
+    months: [
+        &["stycznia", "styczeń", "sty"],
+        &["lutego", "luty", "lut"],
+        &["marca", "marzec", "mar"],
+        &["kwietnia", "kwiecień", "kwi"],
+        &["maja", "maj"],
+        &["czerwca", "czerwiec", "cze"],
+        &["lipca", "lipiec", "lip"],
+        &["sierpnia", "sierpień", "sie"],
+        &["września", "wrzesień", "wrz"],
+        &["października", "październik", "paź"],
+        &["listopada", "listopad", "lis"],
+        &["grudnia", "grudzień", "gru"],
+    ],
+    weekdays: [
+        &["niedziela", "niedz"],
+        &["poniedziałek", "pon"],
+        &["wtorek", "wt"],
+        &["środa", "śr"],
+        &["czwartek", "czw"],
+        &["piątek", "pt"],
+        &["sobota", "sob"],
+    ],
+    periods: [&[], &[]],
+};
+
+const ID: Names = Names {
+    months: [
+        &["Januari", "Jan"],
+        &["Februari", "Feb"],
+        &["Maret", "Mar"],
+        &["April", "Apr"],
+        &["Mei"],
+        &["Juni", "Jun"],
+        &["Juli", "Jul"],
+        &["Agustus", "Agu", "Agt"],
+        &["September", "Sep"],
+        &["Oktober", "Okt"],
+        &["November", "Nov"],
+        &["Desember", "Des"],
+    ],
+    weekdays: [
+        &["Minggu", "Min"],
+        &["Senin", "Sen"],
+        &["Selasa", "Sel"],
+        &["Rabu", "Rab"],
+        &["Kamis", "Kam"],
+        &["Jumat", "Jum"],
+        &["Sabtu", "Sab"],
+    ],
+    periods: [&[], &[]],
+};
+
+const TR: Names = Names {
+    months: [
+        &["Ocak", "Oca"],
+        &["Şubat", "Şub"],
+        &["Mart", "Mar"],
+        &["Nisan", "Nis"],
+        &["Mayıs", "May"],
+        &["Haziran", "Haz"],
+        &["Temmuz", "Tem"],
+        &["Ağustos", "Ağu"],
+        &["Eylül", "Eyl"],
+        &["Ekim", "Eki"],
+        &["Kasım", "Kas"],
+        &["Aralık", "Ara"],
+    ],
+    weekdays: [
+        &["Pazar", "Paz"],
+        &["Pazartesi", "Pzt"],
+        &["Salı", "Sal"],
+        &["Çarşamba", "Çar"],
+        &["Perşembe", "Per"],
+        &["Cuma", "Cum"],
+        &["Cumartesi", "Cmt"],
+    ],
+    periods: [&["ÖÖ"], &["ÖS"]],
+};
+
+const VI: Names = Names {
+    months: [
+        &["tháng 1", "thg 1"],
+        &["tháng 2", "thg 2"],
+        &["tháng 3", "thg 3"],
+        &["tháng 4", "thg 4"],
+        &["tháng 5", "thg 5"],
+        &["tháng 6", "thg 6"],
+        &["tháng 7", "thg 7"],
+        &["tháng 8", "thg 8"],
+        &["tháng 9", "thg 9"],
+        &["tháng 10", "thg 10"],
+        &["tháng 11", "thg 11"],
+        &["tháng 12", "thg 12"],
+    ],
+    weekdays: [
+        &["Chủ Nhật", "CN"],
+        &["Thứ Hai", "Th 2"],
+        &["Thứ Ba", "Th 3"],
+        &["Thứ Tư", "Th 4"],
+        &["Thứ Năm", "Th 5"],
+        &["Thứ Sáu", "Th 6"],
+        &["Thứ Bảy", "Th 7"],
+    ],
+    periods: [&["SA"], &["CH"]],
+};
+
+const JA: Names = Names {
+    months: [
+        &["1月"],
+        &["2月"],
+        &["3月"],
+        &["4月"],
+        &["5月"],
+        &["6月"],
+        &["7月"],
+        &["8月"],
+        &["9月"],
+        &["10月"],
+        &["11月"],
+        &["12月"],
+    ],
+    weekdays: [
+        &["日曜日", "日"],
+        &["月曜日", "月"],
+        &["火曜日", "火"],
+        &["水曜日", "水"],
+        &["木曜日", "木"],
+        &["金曜日", "金"],
+        &["土曜日", "土"],
+    ],
+    periods: [&["午前"], &["午後"]],
+};
+
+const ZH: Names = Names {
+    months: [
+        &["1月", "一月"],
+        &["2月", "二月"],
+        &["3月", "三月"],
+        &["4月", "四月"],
+        &["5月", "五月"],
+        &["6月", "六月"],
+        &["7月", "七月"],
+        &["8月", "八月"],
+        &["9月", "九月"],
+        &["10月", "十月"],
+        &["11月", "十一月"],
+        &["12月", "十二月"],
+    ],
+    weekdays: [
+        &["星期日", "星期天", "周日", "週日"],
+        &["星期一", "周一", "週一"],
+        &["星期二", "周二", "週二"],
+        &["星期三", "周三", "週三"],
+        &["星期四", "周四", "週四"],
+        &["星期五", "周五", "週五"],
+        &["星期六", "周六", "週六"],
+    ],
+    periods: [&["上午"], &["下午"]],
+};
+
+const KO: Names = Names {
+    months: [
+        &["1월"],
+        &["2월"],
+        &["3월"],
+        &["4월"],
+        &["5월"],
+        &["6월"],
+        &["7월"],
+        &["8월"],
+        &["9월"],
+        &["10월"],
+        &["11월"],
+        &["12월"],
+    ],
+    weekdays: [
+        &["일요일", "일"],
+        &["월요일", "월"],
+        &["화요일", "화"],
+        &["수요일", "수"],
+        &["목요일", "목"],
+        &["금요일", "금"],
+        &["토요일", "토"],
+    ],
+    periods: [&["오전"], &["오후"]],
+};
+
+fn names(locale: &str) -> &'static Names {
+    let language = locale
+        .split(['_', '-'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    match language.as_str() {
+        "es" => &ES,
+        "pt" => &PT,
+        "fr" => &FR,
+        "de" => &DE,
+        "it" => &IT,
+        "ru" => &RU,
+        "pl" => &PL,
+        "id" | "in" => &ID,
+        "tr" => &TR,
+        "vi" => &VI,
+        "ja" => &JA,
+        "zh" => &ZH,
+        "ko" => &KO,
+        _ => &EN,
+    }
+}
+
+// Common abbreviations accepted by the `z` field and the timezone argument.
+fn abbreviation_offset(name: &str) -> Option<i32> {
+    let hours = match name.to_uppercase().as_str() {
+        "UTC" | "GMT" | "UT" | "Z" => 0.0,
+        "EST" => -5.0,
+        "EDT" => -4.0,
+        "CST" => -6.0,
+        "CDT" => -5.0,
+        "MST" => -7.0,
+        "MDT" => -6.0,
+        "PST" => -8.0,
+        "PDT" => -7.0,
+        "BST" => 1.0,
+        "CET" => 1.0,
+        "CEST" => 2.0,
+        "EET" => 2.0,
+        "EEST" => 3.0,
+        "MSK" => 3.0,
+        "IST" => 5.5,
+        "WIB" => 7.0,
+        "ICT" => 7.0,
+        "HKT" => 8.0,
+        "SGT" => 8.0,
+        "JST" => 9.0,
+        "KST" => 9.0,
+        "AEST" => 10.0,
+        "AEDT" => 11.0,
+        _ => return None,
+    };
+    Some((hours * 3600.0) as i32)
+}
+
+enum Zone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+fn parse_zone(name: &str) -> Option<Zone> {
+    let name = name.trim();
+    if name.is_empty() {
+        return FixedOffset::east_opt(0).map(Zone::Fixed);
+    }
+    if let Some(offset) = abbreviation_offset(name) {
+        return FixedOffset::east_opt(offset).map(Zone::Fixed);
+    }
+    if let Ok(tz) = Tz::from_str(name) {
+        return Some(Zone::Named(tz));
+    }
+    let mut scanner = Scanner::new(name);
+    match scanner.offset(true) {
+        Some(offset) if scanner.at_end() => FixedOffset::east_opt(offset).map(Zone::Fixed),
+        _ => None,
+    }
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(input: &'a str) -> Self {
+        Scanner { input, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn at_end(&self) -> bool {
+        self.rest().trim().is_empty()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn digits(&mut self, min: usize, max: usize) -> Option<(i64, usize)> {
+        let len = self
+            .rest()
+            .bytes()
+            .take(max)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if len < min || len == 0 {
+            return None;
+        }
+        let value = self.rest()[..len].parse().ok()?;
+        self.pos += len;
+        Some((value, len))
+    }
+
+    // case-insensitive prefix match, returning the byte length matched in the input
+    fn prefix_len(&self, name: &str) -> Option<usize> {
+        let mut input = self.rest().char_indices();
+        for expected in name.chars() {
+            let (_, actual) = input.next()?;
+            if !actual.to_lowercase().eq(expected.to_lowercase()) {
+                return None;
+            }
+        }
+        Some(input.next().map_or(self.rest().len(), |(idx, _)| idx))
+    }
+
+    // Matches the longest of the given spellings, returning the index of its group.
+    fn name<'n>(&mut self, groups: impl Iterator<Item = &'n [&'n str]>) -> Option<usize> {
+        let mut best: Option<(usize, usize)> = None;
+        for (idx, group) in groups.enumerate() {
+            for name in group.iter() {
+                if let Some(len) = self.prefix_len(name) {
+                    if !matches!(best, Some((_, best_len)) if best_len >= len) {
+                        best = Some((idx, len));
+                    }
+                }
+            }
+        }
+        let (idx, len) = best?;
+        self.pos += len;
+        Some(idx)
+    }
+
+    // +hh, +hhmm, +hh:mm, optionally prefixed by GMT/UTC, or a bare Z when allowed
+    fn offset(&mut self, allow_z: bool) -> Option<i32> {
+        if allow_z && (self.eat('Z') || self.eat('z')) {
+            return Some(0);
+        }
+        let prefixed = ["GMT", "UTC"].iter().any(|prefix| {
+            if let Some(len) = self.prefix_len(prefix) {
+                self.pos += len;
+                true
+            } else {
+                false
+            }
+        });
+        let sign = if self.eat('+') {
+            1
+        } else if self.eat('-') || self.eat('−') {
+            -1
+        } else if prefixed {
+            return Some(0);
+        } else {
+            return None;
+        };
+        let (hours, len) = self.digits(1, 2)?;
+        let minutes = if self.eat(':') {
+            self.digits(2, 2)?.0
+        } else if len == 2 {
+            self.digits(2, 2).map_or(0, |(minutes, _)| minutes)
+        } else {
+            0
+        };
+        if hours > 23 || minutes > 59 {
+            return None;
+        }
+        Some(sign * (hours * 3600 + minutes * 60) as i32)
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    year: Option<i32>,
+    month: Option<u32>,
+    day: Option<u32>,
+    day_of_year: Option<u32>,
+    hour: Option<u32>,
+    hour12: bool,
+    pm: Option<bool>,
+    minute: Option<u32>,
+    second: Option<u32>,
+    nanosecond: Option<u32>,
+    offset: Option<i32>,
+}
+
+fn two_digit_year(value: i64) -> i32 {
+    // pick the century that puts the year within 80 years before and 20 after now
+    let current = Utc::now().year();
+    let mut year = current - current % 100 + value as i32;
+    if year > current + 20 {
+        year -= 100;
+    } else if year <= current - 80 {
+        year += 100;
+    }
+    year
+}
+
+/// Parses `input` with an LDML `pattern`, returning a unix timestamp in seconds.
+pub fn parse_date(input: &str, pattern: &str, locale: &str, timezone: &str) -> Option<f64> {
+    let tokens = tokenize(pattern);
+    let names = names(locale);
+    let mut scanner = Scanner::new(input.trim());
+    let mut fields = Fields::default();
+
+    for (idx, token) in tokens.iter().enumerate() {
+        let next = tokens.get(idx + 1);
+        // numeric fields directly followed by another number must use their exact width
+        let width = |count: usize, max: usize| {
+            if is_numeric(next) {
+                (count.max(1), count.max(1))
+            } else {
+                (1, max)
+            }
+        };
+        match token {
+            Token::Literal(literal) => {
+                for c in literal.chars() {
+                    if c.is_whitespace() {
+                        scanner.pos += scanner.rest().len() - scanner.rest().trim_start().len();
+                    } else if !scanner.eat(c) {
+                        return None;
+                    }
+                }
+            }
+            Token::Field(c, count) => {
+                let count = *count;
+                match c {
+                    'y' | 'u' => {
+                        let (min, max) = width(count, 9);
+                        let (value, len) = scanner.digits(min, max)?;
+                        fields.year = Some(if count == 2 && len == 2 {
+                            two_digit_year(value)
+                        } else {
+                            value as i32
+                        });
+                    }
+                    'M' | 'L' if count <= 2 => {
+                        let (min, max) = width(count, 2);
+                        fields.month = Some(scanner.digits(min, max)?.0 as u32);
+                    }
+                    'M' | 'L' => {
+                        let month = scanner
+                            .name(names.months.iter().copied())
+                            .or_else(|| scanner.name(EN.months.iter().copied()))?;
+                        fields.month = Some(month as u32 + 1);
+                        eat_abbreviation_dot(&mut scanner, next);
+                    }
+                    'd' => {
+                        let (min, max) = width(count, 2);
+                        fields.day = Some(scanner.digits(min, max)?.0 as u32);
+                    }
+                    'D' => {
+                        let (min, max) = width(count, 3);
+                        fields.day_of_year = Some(scanner.digits(min, max)?.0 as u32);
+                    }
+                    'E' | 'c' | 'e' if count >= 3 || *c == 'E' => {
+                        scanner
+                            .name(names.weekdays.iter().copied())
+                            .or_else(|| scanner.name(EN.weekdays.iter().copied()))?;
+                        eat_abbreviation_dot(&mut scanner, next);
+                    }
+                    'c' | 'e' => {
+                        scanner.digits(1, count.max(1))?;
+                    }
+                    'a' | 'b' | 'B' => {
+                        let period = scanner
+                            .name(names.periods.iter().copied())
+                            .or_else(|| scanner.name(EN.periods.iter().copied()))?;
+                        fields.pm = Some(period == 1);
+                    }
+                    'H' | 'k' => {
+                        let (min, max) = width(count, 2);
+                        let hour = scanner.digits(min, max)?.0 as u32;
+                        fields.hour = Some(if *c == 'k' && hour == 24 { 0 } else { hour });
+                    }
+                    'h' | 'K' => {
+                        let (min, max) = width(count, 2);
+                        let hour = scanner.digits(min, max)?.0 as u32;
+                        fields.hour = Some(if *c == 'h' && hour == 12 { 0 } else { hour });
+                        fields.hour12 = true;
+                    }
+                    'm' => {
+                        let (min, max) = width(count, 2);
+                        fields.minute = Some(scanner.digits(min, max)?.0 as u32);
+                    }
+                    's' => {
+                        let (min, max) = width(count, 2);
+                        fields.second = Some(scanner.digits(min, max)?.0 as u32);
+                    }
+                    'S' => {
+                        let (min, max) = width(count, 9);
+                        let (value, len) = scanner.digits(min, max)?;
+                        // digits past nanoseconds are dropped
+                        let nanos = if len > 9 {
+                            value / 10i64.pow(len as u32 - 9)
+                        } else {
+                            value * 10i64.pow(9 - len as u32)
+                        };
+                        fields.nanosecond = Some(nanos as u32);
+                    }
+                    'Z' | 'X' | 'x' | 'O' => {
+                        fields.offset = Some(scanner.offset(*c != 'x')?);
+                    }
+                    'z' | 'v' | 'V' => {
+                        let offset = scanner.offset(true).or_else(|| {
+                            let len = scanner
+                                .rest()
+                                .find(|c: char| !c.is_ascii_alphabetic())
+                                .unwrap_or(scanner.rest().len());
+                            let offset = abbreviation_offset(&scanner.rest()[..len])?;
+                            scanner.pos += len;
+                            Some(offset)
+                        })?;
+                        fields.offset = Some(offset);
+                    }
+                    'G' => {
+                        scanner.name([&["AD", "CE"][..], &["BC", "BCE"][..]].iter().copied())?;
+                    }
+                    _ => return None,
+                }
+            }
+        }
+    }
+    if !scanner.at_end() {
+        return None;
+    }
+
+    let year = fields.year.unwrap_or(2000);
+    let date = match (fields.month, fields.day, fields.day_of_year) {
+        (None, None, Some(day_of_year)) => NaiveDate::from_yo_opt(year, day_of_year)?,
+        (month, day, _) => NaiveDate::from_ymd_opt(year, month.unwrap_or(1), day.unwrap_or(1))?,
+    };
+    let mut hour = fields.hour.unwrap_or(0);
+    if fields.hour12 || fields.pm.is_some() {
+        if hour > 12 {
+            return None;
+        }
+        if fields.pm == Some(true) && hour < 12 {
+            hour += 12;
+        } else if fields.pm == Some(false) && hour == 12 {
+            hour = 0;
+        }
+    }
+    let time = NaiveTime::from_hms_nano_opt(
+        hour,
+        fields.minute.unwrap_or(0),
+        fields.second.unwrap_or(0),
+        fields.nanosecond.unwrap_or(0),
+    )?;
+    let local = NaiveDateTime::new(date, time);
+
+    let utc = if let Some(offset) = fields.offset {
+        local - chrono::Duration::seconds(offset as i64)
+    } else {
+        match parse_zone(timezone)? {
+            Zone::Fixed(offset) => offset.from_local_datetime(&local).single()?.naive_utc(),
+            Zone::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                // times skipped by a DST transition are shifted forward, like Foundation does
+                .or_else(|| {
+                    tz.from_local_datetime(&(local + chrono::Duration::hours(1)))
+                        .earliest()
+                })?
+                .naive_utc(),
+        }
+    };
+    Some(utc.timestamp() as f64 + utc.timestamp_subsec_nanos() as f64 / 1e9)
+}
+
+// Abbreviated names are often written with a trailing dot ("ene.", "Sept.").
+fn eat_abbreviation_dot(scanner: &mut Scanner, next: Option<&Token>) {
+    let dot_expected = matches!(next, Some(Token::Literal(literal)) if literal.starts_with('.'));
+    if !dot_expected {
+        scanner.eat('.');
+    }
+}
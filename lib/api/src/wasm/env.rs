@@ -1,7 +1,23 @@
 // use crate::{MangaObject, MangaResult};
+use super::allowlist::Allowlist;
+use super::charset;
+use super::config::NetworkConfig;
+use super::cookies::{self, CookieJar};
+use super::interceptor::Interceptor;
 use super::models::{Chapter, DeepLink, Filter, Listing, Manga, MangaResult, Page, KVC};
+use super::rate_limit::{self, RateLimiter};
+use super::slots::Slots;
+use super::transport::{HttpTransport, NetError, ReqwestTransport};
+use ego_tree::NodeId;
+use indexmap::IndexMap;
+use scraper::Html;
+use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 use wasmer::{LazyInit, Memory, ValueType, WasmPtr, WasmerEnv};
 
 #[derive(Clone, Debug)]
@@ -12,9 +28,11 @@ pub enum WasmObject {
     String(String),
     Bool(bool),
     Array(Vec<WasmObject>),
-    Object(HashMap<String, WasmObject>),
+    // keys stay in insertion order, which for parsed json is document order
+    Object(IndexMap<String, WasmObject>),
     Date(f64),
-    Node,
+    Node(HtmlNode),
+    Image(Vec<u8>),
     Unknown,
 
     Manga(Manga),
@@ -37,11 +55,69 @@ impl WasmObject {
             Self::Array(_) => 5,
             Self::Object(_) => 6,
             Self::Date(_) => 7,
-            Self::Node => 8,
+            Self::Node(_) => 8,
             Self::Unknown => 9,
             _ => 6,
         }
     }
+
+    pub fn kind_name(&self) -> &'static str {
+        match *self {
+            Self::Null => "null",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::String(_) => "string",
+            Self::Bool(_) => "bool",
+            Self::Array(_) => "array",
+            Self::Object(_) => "object",
+            Self::Date(_) => "date",
+            Self::Node(_) => "node",
+            Self::Image(_) => "image",
+            Self::Unknown => "unknown",
+            Self::Manga(_) => "manga",
+            Self::MangaResult(_) => "manga_result",
+            Self::Filter(_) => "filter",
+            Self::Listing(_) => "listing",
+            Self::Chapter(_) => "chapter",
+            Self::Page(_) => "page",
+            Self::DeepLink(_) => "deeplink",
+        }
+    }
+}
+
+impl From<&Value> for WasmObject {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Array(arr) => Self::Array(arr.iter().map(Self::from).collect()),
+            Value::Object(map) => Self::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), Self::from(value)))
+                    .collect(),
+            ),
+            Value::Number(num) => match num.as_i64() {
+                Some(int) => Self::Int(int),
+                None if num.is_f64() => Self::Float(num.as_f64().unwrap_or_default()),
+                None => Self::Null,
+            },
+            Value::String(str) => Self::String(str.clone()),
+            Value::Bool(bool) => Self::Bool(*bool),
+            Value::Null => Self::Null,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HtmlDocument {
+    pub html: Html,
+    pub base_uri: Option<String>,
+}
+
+// A list of nodes inside a parsed document. Selections share the document
+// they came from, so a node stays usable after the document is destroyed.
+#[derive(Clone, Debug)]
+pub struct HtmlNode {
+    pub document: Arc<Mutex<HtmlDocument>>,
+    pub nodes: Vec<NodeId>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -66,16 +142,76 @@ impl From<i32> for HttpMethod {
     }
 }
 
+impl HttpMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Head => "HEAD",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+        }
+    }
+}
+
+// How long a response took, as measured by the transport that sent it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    // from sending the request until its headers arrived
+    pub wait: Duration,
+    // reading the body
+    pub receive: Duration,
+}
+
 #[derive(Clone, Debug)]
 pub struct Response {
     pub status_code: i32,
+    // lowercased names in the order they were received, repeats included
+    pub headers: Vec<(String, String)>,
+    // where the request ended up after following redirects
+    pub url: Option<String>,
     pub data: Vec<u8>,
+    // None when nothing was measured, like for cached responses
+    pub timings: Option<Timings>,
+}
+
+impl Response {
+    // The body decoded with its declared or detected charset.
+    pub fn text(&self) -> String {
+        charset::decode(&self.data, self.header("content-type").as_deref())
+    }
+
+    pub fn header_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // All values of a header joined with ", ".
+    pub fn header(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.header_values(name).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
 }
 
 impl KVC for Response {
     fn get_value(&self, key: String) -> Option<WasmObject> {
         match key.as_str() {
             "status_code" => Some(WasmObject::Int(self.status_code as i64)),
+            "headers" => Some(WasmObject::Object(
+                self.headers
+                    .iter()
+                    .filter_map(|(key, _)| {
+                        Some((key.clone(), WasmObject::String(self.header(key)?)))
+                    })
+                    .collect(),
+            )),
+            "url" => self.url.clone().map(WasmObject::String),
             "data" => Some(WasmObject::Array(
                 self.data
                     .clone()
@@ -95,43 +231,429 @@ pub struct Request {
     pub headers: HashMap<String, Option<String>>,
     pub body: Option<Vec<u8>>,
     pub response: Option<Response>,
+    // set instead of the response when the request couldn't be sent
+    pub error: Option<NetError>,
+    // where the allowlist found the url's domain; transports that connect
+    // themselves should use these instead of looking it up again
+    pub addresses: Option<Vec<SocketAddr>>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    pub fn set_header(&mut self, name: &str, value: String) {
+        self.remove_header(name);
+        self.headers.insert(name.to_string(), Some(value));
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case(name));
+    }
+}
+
+// Location of a child value inside its parent object or array.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValueKey {
+    Key(String),
+    Index(usize),
+}
+
+impl ValueKey {
+    fn index<'a>(&self, obj: &'a WasmObject) -> Option<&'a WasmObject> {
+        match (self, obj) {
+            (Self::Key(key), WasmObject::Object(map)) => map.get(key),
+            (Self::Index(idx), WasmObject::Array(arr)) => arr.get(*idx),
+            _ => None,
+        }
+    }
+
+    fn index_mut<'a>(&self, obj: &'a mut WasmObject) -> Option<&'a mut WasmObject> {
+        match (self, obj) {
+            (Self::Key(key), WasmObject::Object(map)) => map.get_mut(key),
+            (Self::Index(idx), WasmObject::Array(arr)) => arr.get_mut(*idx),
+            _ => None,
+        }
+    }
+
+    fn index_json<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        match (self, value) {
+            (Self::Key(key), Value::Object(map)) => map.get(key),
+            (Self::Index(idx), Value::Array(arr)) => arr.get(*idx),
+            _ => None,
+        }
+    }
+}
+
+fn resolve<'a>(document: &'a Value, path: &[ValueKey]) -> Option<&'a Value> {
+    path.iter()
+        .try_fold(document, |value, key| key.index_json(value))
+}
+
+#[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
+enum StoredValue {
+    Owned(WasmObject),
+    // a view into the parent descriptor, resolved on every access
+    Child(ValueKey),
+    // A value inside a parsed json document, shared with every other
+    // descriptor into the same document. Imports read it in place through
+    // `json_value` and `peek_value`; only a full `read_value` converts it, and
+    // the whole document is converted once anything in it is changed.
+    Json {
+        document: Arc<Value>,
+        path: Vec<ValueKey>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct StdDescriptor {
+    value: StoredValue,
+    parent: Option<i32>,
+    children: Vec<i32>,
+    import: Option<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+struct RequestDescriptor {
+    request: Request,
+    import: Option<&'static str>,
+}
+
+// Descriptors created while a top-level source call is running.
+#[derive(Clone, Debug)]
+struct Arena {
+    call: &'static str,
+    values: Vec<i32>,
+    requests: Vec<i32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LeakedDescriptor {
+    pub descriptor: i32,
+    pub kind: &'static str,
+    // the import that created the descriptor, or None if the host did
+    pub import: Option<&'static str>,
+}
+
+#[derive(Clone, Debug)]
+pub struct LeakReport {
+    pub call: &'static str,
+    pub values: Vec<LeakedDescriptor>,
+    pub requests: Vec<LeakedDescriptor>,
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} leaked {} value(s) and {} request(s)",
+            self.call,
+            self.values.len(),
+            self.requests.len()
+        )?;
+        for leak in self.values.iter().chain(self.requests.iter()) {
+            write!(
+                f,
+                "\n  {} ({}) created by {}",
+                leak.descriptor,
+                leak.kind,
+                leak.import.unwrap_or("the host")
+            )?;
+        }
+        Ok(())
+    }
 }
 
 pub struct WasmGlobalStore {
-    std_pointer: i32,
-    std_descriptors: HashMap<i32, WasmObject>,
-    request_pointer: i32,
-    requests: HashMap<i32, Request>,
+    std_descriptors: Slots<StdDescriptor>,
+    requests: Slots<RequestDescriptor>,
+    arenas: Vec<Arena>,
+    reclaimed: usize,
+    pub leak_check: bool,
+    leaks: Vec<LeakReport>,
+    // the import holding the lock, recorded on descriptors it creates
+    import: Option<&'static str>,
     pub defaults: HashMap<String, WasmObject>,
 }
 
+impl Default for WasmGlobalStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WasmGlobalStore {
     pub fn new() -> WasmGlobalStore {
         WasmGlobalStore {
-            std_pointer: -1,
-            std_descriptors: HashMap::new(),
-            request_pointer: -1,
-            requests: HashMap::new(),
+            std_descriptors: Slots::new(),
+            requests: Slots::new(),
+            arenas: Vec::new(),
+            reclaimed: 0,
+            leak_check: false,
+            leaks: Vec::new(),
+            import: None,
             defaults: HashMap::new(),
         }
     }
 
-    pub fn read_value(&self, descriptor: i32) -> Option<&WasmObject> {
-        self.std_descriptors.get(&descriptor)
+    // Json values are converted for each read, so callers that only need
+    // part of one should look at `json_value` first.
+    pub fn read_value(&self, descriptor: i32) -> Option<Cow<'_, WasmObject>> {
+        let entry = self.std_descriptors.get(descriptor)?;
+        match &entry.value {
+            StoredValue::Owned(obj) => Some(Cow::Borrowed(obj)),
+            StoredValue::Child(key) => match self.read_value(entry.parent?)? {
+                Cow::Borrowed(obj) => key.index(obj).map(Cow::Borrowed),
+                Cow::Owned(obj) => key.index(&obj).cloned().map(Cow::Owned),
+            },
+            StoredValue::Json { document, path } => {
+                Some(Cow::Owned(resolve(document, path)?.into()))
+            }
+        }
+    }
+
+    // The parsed json behind a descriptor made by `store_json` or one of its
+    // children, for reading it without converting.
+    pub fn json_value(&self, descriptor: i32) -> Option<&Value> {
+        match &self.std_descriptors.get(descriptor)?.value {
+            StoredValue::Json { document, path, .. } => resolve(document, path),
+            _ => None,
+        }
+    }
+
+    // Enough of a value to tell its type. Json arrays and objects come back
+    // empty rather than being converted.
+    pub fn peek_value(&self, descriptor: i32) -> Option<Cow<'_, WasmObject>> {
+        match self.json_value(descriptor) {
+            Some(Value::Array(_)) => Some(Cow::Owned(WasmObject::Array(Vec::new()))),
+            Some(Value::Object(_)) => Some(Cow::Owned(WasmObject::Object(IndexMap::new()))),
+            _ => self.read_value(descriptor),
+        }
+    }
+
+    pub fn value_mut(&mut self, descriptor: i32) -> Option<&mut WasmObject> {
+        self.materialize(descriptor);
+        let (root, path) = self.owner_path(descriptor)?;
+        let mut obj = match &mut self.std_descriptors.get_mut(root)?.value {
+            StoredValue::Owned(obj) => obj,
+            StoredValue::Child(_) | StoredValue::Json { .. } => return None,
+        };
+        for key in &path {
+            obj = key.index_mut(obj)?;
+        }
+        Some(obj)
+    }
+
+    // The descriptor that owns the data `descriptor` refers to, and the keys
+    // leading from it down to the value.
+    fn owner_path(&self, descriptor: i32) -> Option<(i32, Vec<ValueKey>)> {
+        let mut path = Vec::new();
+        let mut root = descriptor;
+        while let StoredValue::Child(key) = &self.std_descriptors.get(root)?.value {
+            path.push(key.clone());
+            root = self.std_descriptors.get(root)?.parent?;
+        }
+        path.reverse();
+        Some((root, path))
+    }
+
+    // Keeps child descriptors pointing at the same elements after the object
+    // or array behind `descriptor` changed shape. `rekey` maps each child's key
+    // to its new one, or to None when its element was removed or replaced, in
+    // which case the child is destroyed rather than left resolving to
+    // something else.
+    pub fn rekey_children(
+        &mut self,
+        descriptor: i32,
+        rekey: impl Fn(&ValueKey) -> Option<ValueKey>,
+    ) {
+        let (root, target) = match self.owner_path(descriptor) {
+            Some(owner) => owner,
+            None => return,
+        };
+        // every view into the value hangs off its owner, possibly through
+        // other views of the same value
+        let mut stale = Vec::new();
+        let mut pending = vec![(root, Vec::new())];
+        while let Some((parent, path)) = pending.pop() {
+            let children = match self.std_descriptors.get(parent) {
+                Some(entry) => entry.children.clone(),
+                None => continue,
+            };
+            for child in children {
+                let entry = match self.std_descriptors.get_mut(child) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                let key = match &entry.value {
+                    StoredValue::Child(key) => key.clone(),
+                    _ => continue,
+                };
+                if path == target {
+                    match rekey(&key) {
+                        Some(new_key) => entry.value = StoredValue::Child(new_key),
+                        None => stale.push(child),
+                    }
+                } else {
+                    let mut path = path.clone();
+                    path.push(key);
+                    if target.starts_with(&path) {
+                        pending.push((child, path));
+                    }
+                }
+            }
+        }
+        for child in stale {
+            self.remove_value(child);
+        }
     }
 
-    pub fn store_value(&mut self, obj: WasmObject, _from: Option<i32>) -> i32 {
-        self.std_pointer += 1;
-        self.std_descriptors.insert(self.std_pointer, obj);
-        self.std_pointer
+    pub fn store_value(&mut self, obj: WasmObject, from: Option<i32>) -> i32 {
+        self.insert_descriptor(StoredValue::Owned(obj), from)
+    }
+
+    // Stores a parsed json document without converting it. Children taken
+    // from it share the document instead of copying out of it.
+    pub fn store_json(&mut self, document: Value) -> i32 {
+        self.insert_descriptor(
+            StoredValue::Json {
+                document: Arc::new(document),
+                path: Vec::new(),
+            },
+            None,
+        )
+    }
+
+    // Stores a descriptor referring to `key` inside `parent`. The child reads
+    // through to the parent, so mutations on either side are visible to both,
+    // and it is destroyed along with the parent.
+    pub fn store_child(&mut self, parent: i32, key: ValueKey) -> i32 {
+        if let Some(StoredValue::Json { document, path, .. }) =
+            self.std_descriptors.get(parent).map(|entry| &entry.value)
+        {
+            let document = document.clone();
+            let mut path = path.clone();
+            path.push(key);
+            if resolve(&document, &path).is_none() {
+                return -1;
+            }
+            return self.insert_descriptor(StoredValue::Json { document, path }, Some(parent));
+        }
+        match self.read_value(parent) {
+            Some(obj) if key.index(&obj).is_some() => {
+                self.insert_descriptor(StoredValue::Child(key), Some(parent))
+            }
+            _ => -1,
+        }
+    }
+
+    fn insert_descriptor(&mut self, value: StoredValue, from: Option<i32>) -> i32 {
+        let parent = from.filter(|parent| self.std_descriptors.contains(*parent));
+        let descriptor = self.std_descriptors.insert(StdDescriptor {
+            value,
+            parent,
+            children: Vec::new(),
+            import: self.import,
+        });
+        if descriptor != -1 {
+            if let Some(parent) = parent.and_then(|p| self.std_descriptors.get_mut(p)) {
+                parent.children.push(descriptor);
+            }
+            if let Some(arena) = self.arenas.last_mut() {
+                arena.values.push(descriptor);
+            }
+        }
+        descriptor
+    }
+
+    // Converts the json document `descriptor` points into, so that it can be
+    // changed. The descriptor at the top of the document takes ownership of
+    // the converted values and the rest become views into it, as if they had
+    // never been lazy.
+    fn materialize(&mut self, descriptor: i32) {
+        let document = match self
+            .std_descriptors
+            .get(descriptor)
+            .map(|entry| &entry.value)
+        {
+            Some(StoredValue::Json { document, .. }) => document.clone(),
+            _ => return,
+        };
+        let same_document = |entry: &StdDescriptor| matches!(&entry.value, StoredValue::Json { document: other, .. } if Arc::ptr_eq(other, &document));
+        let mut root = descriptor;
+        while let Some(parent) = self
+            .std_descriptors
+            .get(root)
+            .and_then(|entry| entry.parent)
+        {
+            match self.std_descriptors.get(parent) {
+                Some(entry) if same_document(entry) => root = parent,
+                _ => break,
+            }
+        }
+
+        let entry = match self.std_descriptors.get_mut(root) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let obj = match &mut entry.value {
+            StoredValue::Json { path, .. } => match resolve(&document, path) {
+                Some(json) => json.into(),
+                None => return,
+            },
+            _ => return,
+        };
+        entry.value = StoredValue::Owned(obj);
+
+        let mut children = entry.children.clone();
+        while let Some(child) = children.pop() {
+            let entry = match self.std_descriptors.get_mut(child) {
+                Some(entry) if same_document(entry) => entry,
+                _ => continue,
+            };
+            if let StoredValue::Json { path, .. } = &entry.value {
+                if let Some(key) = path.last().cloned() {
+                    entry.value = StoredValue::Child(key);
+                    children.extend(entry.children.iter().copied());
+                }
+            }
+        }
     }
 
+    // Replaces the value behind a live descriptor. Descriptors that are stale
+    // or were never handed out are ignored rather than created, since a
+    // recycled slot could otherwise be revived under a descriptor the source
+    // already destroyed.
     pub fn set_value(&mut self, descriptor: i32, obj: WasmObject) {
-        self.std_descriptors.insert(descriptor, obj);
+        if let Some(value) = self.value_mut(descriptor) {
+            *value = obj;
+        }
     }
 
     pub fn remove_value(&mut self, descriptor: i32) {
-        self.std_descriptors.remove(&descriptor);
+        let entry = match self.std_descriptors.remove(descriptor) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if let Some(parent) = entry.parent.and_then(|p| self.std_descriptors.get_mut(p)) {
+            parent.children.retain(|child| *child != descriptor);
+        }
+        let mut children = entry.children;
+        while let Some(child) = children.pop() {
+            if let Some(entry) = self.std_descriptors.remove(child) {
+                children.extend(entry.children);
+            }
+        }
+    }
+
+    pub fn value_count(&self) -> usize {
+        self.std_descriptors.len()
     }
 }
 
@@ -143,22 +665,114 @@ impl WasmGlobalStore {
             headers: HashMap::new(),
             body: None,
             response: None,
+            error: None,
+            addresses: None,
         };
-        self.request_pointer += 1;
-        self.requests.insert(self.request_pointer, request);
-        self.request_pointer
+        let descriptor = self.requests.insert(RequestDescriptor {
+            request,
+            import: self.import,
+        });
+        if descriptor != -1 {
+            if let Some(arena) = self.arenas.last_mut() {
+                arena.requests.push(descriptor);
+            }
+        }
+        descriptor
     }
 
     pub fn get_request(&self, descriptor: &i32) -> Option<&Request> {
-        self.requests.get(descriptor)
+        self.requests.get(*descriptor).map(|entry| &entry.request)
     }
 
     pub fn set_request(&mut self, descriptor: i32, request: Request) {
-        self.requests.insert(descriptor, request);
+        if let Some(entry) = self.requests.get_mut(descriptor) {
+            entry.request = request;
+        }
     }
 
     pub fn remove_request(&mut self, descriptor: i32) {
-        self.requests.remove(&descriptor);
+        self.requests.remove(descriptor);
+    }
+
+    pub fn request_count(&self) -> usize {
+        self.requests.len()
+    }
+}
+
+impl WasmGlobalStore {
+    pub fn open_arena(&mut self, call: &'static str) {
+        self.arenas.push(Arena {
+            call,
+            values: Vec::new(),
+            requests: Vec::new(),
+        });
+    }
+
+    // Frees everything created since the matching `open_arena` that the source
+    // didn't destroy itself, returning how many descriptors were reclaimed.
+    pub fn close_arena(&mut self) -> usize {
+        let arena = match self.arenas.pop() {
+            Some(arena) => arena,
+            None => return 0,
+        };
+        if self.leak_check {
+            self.check_leaks(&arena);
+        }
+        let count = self.value_count() + self.request_count();
+        for descriptor in arena.values {
+            self.remove_value(descriptor);
+        }
+        for descriptor in arena.requests {
+            self.remove_request(descriptor);
+        }
+        let reclaimed = count - self.value_count() - self.request_count();
+        self.reclaimed += reclaimed;
+        reclaimed
+    }
+
+    pub fn reclaimed_count(&self) -> usize {
+        self.reclaimed
+    }
+
+    fn check_leaks(&mut self, arena: &Arena) {
+        let values: Vec<LeakedDescriptor> = arena
+            .values
+            .iter()
+            .filter_map(|descriptor| {
+                let entry = self.std_descriptors.get(*descriptor)?;
+                Some(LeakedDescriptor {
+                    descriptor: *descriptor,
+                    kind: self
+                        .peek_value(*descriptor)
+                        .map_or("unknown", |obj| obj.kind_name()),
+                    import: entry.import,
+                })
+            })
+            .collect();
+        let requests: Vec<LeakedDescriptor> = arena
+            .requests
+            .iter()
+            .filter_map(|descriptor| {
+                let entry = self.requests.get(*descriptor)?;
+                Some(LeakedDescriptor {
+                    descriptor: *descriptor,
+                    kind: "request",
+                    import: entry.import,
+                })
+            })
+            .collect();
+        if !values.is_empty() || !requests.is_empty() {
+            let report = LeakReport {
+                call: arena.call,
+                values,
+                requests,
+            };
+            self.leaks.push(report);
+        }
+    }
+
+    pub fn take_leaks(&mut self) -> Vec<LeakReport> {
+        std::mem::take(&mut self.leaks)
     }
 }
 
@@ -167,6 +781,20 @@ pub struct WasmEnv {
     #[wasmer(export)]
     pub memory: LazyInit<Memory>,
     pub store: Arc<Mutex<WasmGlobalStore>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub cookies: Arc<CookieJar>,
+    pub network: Arc<NetworkConfig>,
+    pub allowlist: Arc<Allowlist>,
+    pub transport: Arc<dyn HttpTransport>,
+    pub interceptor: Option<Arc<dyn Interceptor>>,
+    // set on the copy each import is registered with
+    pub import: Option<&'static str>,
+}
+
+impl Default for WasmEnv {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WasmEnv {
@@ -174,22 +802,60 @@ impl WasmEnv {
         Self {
             memory: Default::default(),
             store: Arc::new(Mutex::new(WasmGlobalStore::new())),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            cookies: Arc::new(CookieJar::new()),
+            network: Arc::new(NetworkConfig::default()),
+            allowlist: Arc::new(Allowlist::new()),
+            transport: Arc::new(ReqwestTransport::new()),
+            interceptor: None,
+            import: None,
+        }
+    }
+
+    // A copy for registering the import `name` with, so that descriptors it
+    // creates can be traced back to it.
+    pub fn named(&self, name: &'static str) -> Self {
+        Self {
+            import: Some(name),
+            ..self.clone()
+        }
+    }
+
+    // Replaces the transport with a client built from `config`, so wrap the
+    // transport only after calling this.
+    pub fn with_network(mut self, config: NetworkConfig) -> Self {
+        self.transport = Arc::new(ReqwestTransport::from_config(config.clone()));
+        self.network = Arc::new(config);
+        self
+    }
+
+    // An env whose network limits and cookies are shared with every other
+    // instance of the same source.
+    pub fn for_source(id: &str) -> Self {
+        Self {
+            rate_limiter: rate_limit::shared(id),
+            cookies: cookies::shared(id),
+            ..Self::new()
         }
     }
 
-    pub fn store(&self) -> MutexGuard<WasmGlobalStore> {
-        self.store.lock().unwrap()
+    pub fn store(&self) -> MutexGuard<'_, WasmGlobalStore> {
+        let mut store = self.store.lock().unwrap();
+        store.import = self.import;
+        store
     }
 
     pub fn memory(&self) -> &Memory {
         self.memory_ref().unwrap()
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn read_string(&self, ptr: u32, len: u32) -> Result<String, ()> {
         let input: Vec<u8> = self.read_bytes(ptr, len)?;
         Ok(String::from_utf8_lossy(&input).to_string())
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn read_bytes(&self, ptr: u32, len: u32) -> Result<Vec<u8>, ()> {
         let offset: WasmPtr<u8, wasmer::Array> = WasmPtr::new(ptr);
         if let Some(buf) = offset.deref(self.memory(), 0, len) {
@@ -199,6 +865,7 @@ impl WasmEnv {
         }
     }
 
+    #[allow(clippy::result_unit_err)]
     pub fn read_values<T>(&self, ptr: u32, len: u32) -> Result<Vec<T>, ()>
     where
         T: ValueType,
@@ -221,7 +888,7 @@ impl WasmEnv {
             _ => return,
         };
         let from = offset as usize;
-        for (bytes, cell) in value.into_iter().zip(view[from..from + value.len()].iter()) {
+        for (bytes, cell) in value.iter().zip(view[from..from + value.len()].iter()) {
             cell.set(*bytes);
         }
     }
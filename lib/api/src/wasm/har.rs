@@ -0,0 +1,282 @@
+// Captures source traffic as a HAR 1.2 log that can be opened in browser
+// devtools.
+//
+// The recorder wraps another transport, so every request `net::send` makes,
+// including each redirect hop, becomes one entry.
+
+use super::env::{Request, Response};
+use super::transport::{Capped, HttpTransport, Tee};
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use url::Url;
+
+pub struct HarRecorder {
+    inner: Arc<dyn HttpTransport>,
+    // bodies longer than this many bytes are cut short
+    body_limit: Option<usize>,
+    entries: Mutex<Vec<Value>>,
+}
+
+impl HarRecorder {
+    pub fn new(inner: Arc<dyn HttpTransport>) -> Self {
+        HarRecorder {
+            inner,
+            body_limit: None,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn with_body_limit(mut self, limit: Option<usize>) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn har(&self) -> Value {
+        json!({
+            "log": {
+                "version": "1.2",
+                "creator": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "pages": [],
+                "entries": self.entries.lock().unwrap().clone(),
+            }
+        })
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(&self.har())?)
+    }
+
+    // `streamed` is the start of a download's body, which isn't in the
+    // response.
+    fn entry(
+        &self,
+        request: &Request,
+        result: Result<&Response, &anyhow::Error>,
+        streamed: Option<&Capped>,
+        started: DateTime<Utc>,
+        time: f64,
+    ) -> Value {
+        let url = request.url.clone().unwrap_or_default();
+        let mut headers: Vec<(String, String)> = request
+            .headers
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.clone()?)))
+            .collect();
+        headers.sort();
+        let query: Vec<Value> = Url::parse(&url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| json!({ "name": name, "value": value }))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let cookies: Vec<Value> = request
+            .header("cookie")
+            .map(|cookies| cookies.split(';').filter_map(cookie).collect())
+            .unwrap_or_default();
+
+        let mut har_request = json!({
+            "method": request.method.as_str(),
+            "url": url,
+            "httpVersion": "HTTP/1.1",
+            "cookies": cookies,
+            "headers": name_values(&headers),
+            "queryString": query,
+            "headersSize": -1,
+            "bodySize": request.body.as_ref().map_or(0, |body| body.len()),
+        });
+        if let Some(body) = &request.body {
+            let mime_type = request.header("content-type").unwrap_or_default();
+            let (text, _, _) = self.body_text(body, body.len());
+            har_request["postData"] = json!({ "mimeType": mime_type, "text": text });
+        }
+
+        let har_response = match result {
+            Ok(response) => {
+                let (data, size) = match streamed {
+                    Some(copy) if !copy.is_empty() => (copy.data(), copy.len()),
+                    _ => (response.data.as_slice(), response.data.len()),
+                };
+                let mime_type = response.header("content-type").unwrap_or_default();
+                let mut content = json!({
+                    "size": size,
+                    "mimeType": mime_type,
+                });
+                let (text, encoding, truncated) = self.body_text(data, size);
+                content["text"] = json!(text);
+                if let Some(encoding) = encoding {
+                    content["encoding"] = json!(encoding);
+                }
+                if truncated {
+                    content["comment"] = json!(format!("truncated from {} bytes", size));
+                }
+                let cookies: Vec<Value> = response
+                    .header_values("set-cookie")
+                    .filter_map(|header| cookie(header.split(';').next()?))
+                    .collect();
+                let status_text = StatusCode::from_u16(response.status_code as u16)
+                    .ok()
+                    .and_then(|status| status.canonical_reason())
+                    .unwrap_or_default();
+                json!({
+                    "status": response.status_code,
+                    "statusText": status_text,
+                    "httpVersion": "HTTP/1.1",
+                    "cookies": cookies,
+                    "headers": name_values(&response.headers),
+                    "content": content,
+                    "redirectURL": response.header("location").unwrap_or_default(),
+                    "headersSize": -1,
+                    "bodySize": size,
+                })
+            }
+            // devtools shows entries with status 0 as failed
+            Err(err) => json!({
+                "status": 0,
+                "statusText": "",
+                "httpVersion": "HTTP/1.1",
+                "cookies": [],
+                "headers": [],
+                "content": { "size": 0, "mimeType": "" },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+                "_error": err.to_string(),
+            }),
+        };
+
+        // the transport measures when the headers arrived and how long the
+        // body took; connecting isn't broken out, so it counts as waiting
+        let timings = match result.ok().and_then(|response| response.timings) {
+            Some(timings) => {
+                let receive = (timings.receive.as_secs_f64() * 1000.0).min(time);
+                json!({
+                    "blocked": -1,
+                    "dns": -1,
+                    "connect": -1,
+                    "send": 0,
+                    "wait": time - receive,
+                    "receive": receive,
+                })
+            }
+            None => json!({
+                "blocked": -1,
+                "dns": -1,
+                "connect": -1,
+                "send": 0,
+                "wait": time,
+                "receive": 0,
+                "comment": "not measured by the transport",
+            }),
+        };
+        json!({
+            "startedDateTime": started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "time": time,
+            "request": har_request,
+            "response": har_response,
+            "cache": {},
+            "timings": timings,
+        })
+    }
+
+    // Returns the body as text (or base64 when it isn't UTF-8), the encoding
+    // used, and whether it was truncated. `body` may already be cut short of
+    // its full `size`.
+    fn body_text(&self, body: &[u8], size: usize) -> (String, Option<&'static str>, bool) {
+        let limit = self.body_limit.unwrap_or(body.len()).min(body.len());
+        let truncated = limit < size;
+        // a cut can land inside a character
+        let text = match std::str::from_utf8(&body[..limit]) {
+            Ok(text) => Some(text),
+            Err(err) if err.error_len().is_none() && truncated => {
+                std::str::from_utf8(&body[..err.valid_up_to()]).ok()
+            }
+            Err(_) => None,
+        };
+        match text {
+            Some(text) => (text.to_string(), None, truncated),
+            None => (base64::encode(&body[..limit]), Some("base64"), truncated),
+        }
+    }
+}
+
+fn name_values(pairs: &[(String, String)]) -> Vec<Value> {
+    pairs
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn cookie(pair: &str) -> Option<Value> {
+    let (name, value) = pair.split_once('=')?;
+    Some(json!({ "name": name.trim(), "value": value.trim() }))
+}
+
+impl HarRecorder {
+    fn push(
+        &self,
+        request: &Request,
+        result: Result<Response>,
+        streamed: Option<Capped>,
+        started: DateTime<Utc>,
+        start: Instant,
+    ) -> Result<Response> {
+        let time = start.elapsed().as_secs_f64() * 1000.0;
+        let entry = self.entry(request, result.as_ref(), streamed.as_ref(), started, time);
+        self.entries.lock().unwrap().push(entry);
+        result
+    }
+}
+
+impl HttpTransport for HarRecorder {
+    fn send(&self, request: &Request) -> Result<Response> {
+        self.exchange(request, None, &|| {})
+    }
+
+    fn download(&self, request: &Request, body: &mut dyn Write) -> Result<Response> {
+        self.exchange(request, Some(body), &|| {})
+    }
+
+    fn exchange(
+        &self,
+        request: &Request,
+        body: Option<&mut dyn Write>,
+        ready: &dyn Fn(),
+    ) -> Result<Response> {
+        // waiting for the rate limiter isn't part of the request's time
+        let begun = Cell::new((Utc::now(), Instant::now()));
+        let ready = || {
+            ready();
+            begun.set((Utc::now(), Instant::now()));
+        };
+        match body {
+            Some(body) => {
+                // only as much of the body as the entry will show is kept
+                let mut tee = Tee::new(body, Capped::new(self.body_limit));
+                let result = self.inner.exchange(request, Some(&mut tee), &ready);
+                let (started, start) = begun.get();
+                self.push(request, result, tee.into_copy(), started, start)
+            }
+            None => {
+                let result = self.inner.exchange(request, None, &ready);
+                let (started, start) = begun.get();
+                self.push(request, result, None, started, start)
+            }
+        }
+    }
+}
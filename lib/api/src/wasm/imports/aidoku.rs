@@ -1,5 +1,6 @@
 use super::wasm::env::{WasmEnv, WasmObject};
 use super::wasm::models::{self, Chapter, DeepLink, Manga, MangaResult, Page};
+use std::borrow::Cow;
 
 fn read_str(env: &WasmEnv, ptr: u32, len: u32) -> Option<String> {
     if len > 0 {
@@ -9,6 +10,7 @@ fn read_str(env: &WasmEnv, ptr: u32, len: u32) -> Option<String> {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_manga(
     env: &WasmEnv,
     id: u32,
@@ -68,29 +70,25 @@ pub fn create_manga(
 pub fn create_manga_result(env: &WasmEnv, manga_arr: i32, has_more: i32) -> i32 {
     // println!("create_manga_result()");
     let mut store = env.store();
-    if let Some(arr) = store.read_value(manga_arr) {
-        match arr {
-            WasmObject::Array(arr) => {
-                let manga: Vec<Manga> = arr
-                    .into_iter()
-                    .filter_map(|o| match o {
-                        WasmObject::Manga(m) => Some(m.clone()),
-                        _ => None,
-                    })
-                    .collect();
-                let result = MangaResult {
-                    manga,
-                    has_more: has_more == 1,
-                };
-                store.store_value(WasmObject::MangaResult(result), None)
-            }
-            _ => -1,
-        }
+    if let Some(WasmObject::Array(arr)) = store.peek_value(manga_arr).as_deref() {
+        let manga: Vec<Manga> = arr
+            .iter()
+            .filter_map(|o| match o {
+                WasmObject::Manga(m) => Some(m.clone()),
+                _ => None,
+            })
+            .collect();
+        let result = MangaResult {
+            manga,
+            has_more: has_more == 1,
+        };
+        store.store_value(WasmObject::MangaResult(result), None)
     } else {
         -1
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_chapter(
     env: &WasmEnv,
     id: u32,
@@ -128,6 +126,7 @@ pub fn create_chapter(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_page(
     env: &WasmEnv,
     index: i32,
@@ -150,12 +149,15 @@ pub fn create_page(
 pub fn create_deeplink(env: &WasmEnv, manga: i32, chapter: i32) -> i32 {
     let store = env.store();
     let deeplink = DeepLink {
-        manga: if let Some(WasmObject::Manga(manga)) = store.read_value(manga).cloned() {
+        manga: if let Some(WasmObject::Manga(manga)) = store.peek_value(manga).map(Cow::into_owned)
+        {
             Some(manga)
         } else {
             None
         },
-        chapter: if let Some(WasmObject::Chapter(chapter)) = store.read_value(chapter).cloned() {
+        chapter: if let Some(WasmObject::Chapter(chapter)) =
+            store.peek_value(chapter).map(Cow::into_owned)
+        {
             Some(chapter)
         } else {
             None
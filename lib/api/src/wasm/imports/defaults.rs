@@ -1,4 +1,5 @@
 use super::wasm::env::WasmEnv;
+use std::borrow::Cow;
 
 pub fn get(env: &WasmEnv, key: u32, len: u32) -> i32 {
     if let Ok(key) = env.read_string(key, len) {
@@ -16,7 +17,7 @@ pub fn get(env: &WasmEnv, key: u32, len: u32) -> i32 {
 pub fn set(env: &WasmEnv, key: u32, len: u32, value: i32) {
     if let Ok(key) = env.read_string(key, len) {
         let mut store = env.store();
-        if let Some(value) = store.read_value(value).cloned() {
+        if let Some(value) = store.read_value(value).map(Cow::into_owned) {
             store.defaults.insert(key, value);
         }
     }
@@ -0,0 +1,595 @@
+use super::wasm::charset;
+use super::wasm::env::{HtmlDocument, HtmlNode, WasmEnv, WasmObject};
+use ego_tree::iter::Edge;
+use ego_tree::{NodeId, NodeRef};
+use markup5ever::data::NAMED_ENTITIES;
+use scraper::{CaseSensitivity, ElementRef, Html, Node, Selector};
+use std::collections::HashSet;
+use std::iter;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+// parse
+pub fn parse(env: &WasmEnv, data: u32, len: u32) -> i32 {
+    parse_with_uri(env, data, len, 0, 0)
+}
+pub fn parse_with_uri(env: &WasmEnv, data: u32, len: u32, uri: u32, uri_len: u32) -> i32 {
+    if let Ok(data) = env.read_bytes(data, len) {
+        let str = charset::decode(&data, None);
+        let document = parse_document(&str, read_uri(env, uri, uri_len));
+        env.store().store_value(document, None)
+    } else {
+        -1
+    }
+}
+pub fn parse_fragment(env: &WasmEnv, data: u32, len: u32) -> i32 {
+    parse_fragment_with_uri(env, data, len, 0, 0)
+}
+pub fn parse_fragment_with_uri(env: &WasmEnv, data: u32, len: u32, uri: u32, uri_len: u32) -> i32 {
+    if let Ok(data) = env.read_bytes(data, len) {
+        let html = Html::parse_fragment(&charset::decode(&data, None));
+        // fragments are wrapped in an <html> element, which stands in for the body
+        let root = html.root_element().id();
+        let node = new_document(html, read_uri(env, uri, uri_len), root);
+        env.store().store_value(node, None)
+    } else {
+        -1
+    }
+}
+
+// Parsed without the store, which other imports can use in the meantime.
+pub fn parse_document(str: &str, base_uri: Option<String>) -> WasmObject {
+    let html = Html::parse_document(str);
+    let root = html.tree.root().id();
+    new_document(html, base_uri, root)
+}
+
+fn read_uri(env: &WasmEnv, uri: u32, len: u32) -> Option<String> {
+    if len > 0 {
+        env.read_string(uri, len).ok()
+    } else {
+        None
+    }
+}
+
+fn new_document(html: Html, base_uri: Option<String>, root: NodeId) -> WasmObject {
+    // a <base href> in the document takes precedence, like in jsoup
+    let base_href = Selector::parse("base[href]")
+        .ok()
+        .and_then(|selector| {
+            html.select(&selector)
+                .next()
+                .and_then(|base| base.value().attr("href"))
+                .map(String::from)
+        })
+        .and_then(|href| resolve(base_uri.as_deref(), &href));
+    WasmObject::Node(HtmlNode {
+        document: Arc::new(Mutex::new(HtmlDocument {
+            html,
+            base_uri: base_href.or(base_uri),
+        })),
+        nodes: vec![root],
+    })
+}
+
+fn resolve(base: Option<&str>, url: &str) -> Option<String> {
+    match base.and_then(|base| Url::parse(base).ok()) {
+        Some(base) => base.join(url.trim()).ok(),
+        None => Url::parse(url.trim()).ok(),
+    }
+    .map(String::from)
+}
+
+fn read_node(env: &WasmEnv, descriptor: i32) -> Option<HtmlNode> {
+    match env.store().peek_value(descriptor).as_deref() {
+        Some(WasmObject::Node(node)) => Some(node.clone()),
+        _ => None,
+    }
+}
+
+// Runs `f` with the nodes behind a descriptor while their document is locked.
+fn with_nodes<T>(
+    env: &WasmEnv,
+    descriptor: i32,
+    f: impl FnOnce(&HtmlDocument, Vec<NodeRef<Node>>) -> T,
+) -> Option<T> {
+    read_node(env, descriptor).map(|node| lock_nodes(&node, f))
+}
+
+fn lock_nodes<T>(node: &HtmlNode, f: impl FnOnce(&HtmlDocument, Vec<NodeRef<Node>>) -> T) -> T {
+    let document = node.document.lock().unwrap();
+    let nodes = node
+        .nodes
+        .iter()
+        .filter_map(|id| document.html.tree.get(*id))
+        .collect();
+    f(&document, nodes)
+}
+
+// Stores a new selection in the same document as `descriptor`.
+fn derive(
+    env: &WasmEnv,
+    descriptor: i32,
+    f: impl FnOnce(&HtmlDocument, Vec<NodeRef<Node>>) -> Option<Vec<NodeId>>,
+) -> i32 {
+    let node = match read_node(env, descriptor) {
+        Some(node) => node,
+        None => return -1,
+    };
+    match lock_nodes(&node, f) {
+        Some(nodes) => env.store().store_value(
+            WasmObject::Node(HtmlNode {
+                document: node.document,
+                nodes,
+            }),
+            None,
+        ),
+        None => -1,
+    }
+}
+
+fn store_string(env: &WasmEnv, value: Option<String>) -> i32 {
+    match value {
+        Some(str) => env.store().store_value(WasmObject::String(str), None),
+        None => -1,
+    }
+}
+
+// select
+pub fn select(env: &WasmEnv, descriptor: i32, selector: u32, len: u32) -> i32 {
+    let selector = match env
+        .read_string(selector, len)
+        .ok()
+        .and_then(|str| Selector::parse(&str).ok())
+    {
+        Some(selector) => selector,
+        None => return -1,
+    };
+    derive(env, descriptor, |_, nodes| {
+        // matches anywhere under (or at) the selected nodes, in document order
+        Some(
+            outermost(nodes)
+                .iter()
+                .flat_map(|node| node.descendants())
+                .filter_map(ElementRef::wrap)
+                .filter(|element| selector.matches(element))
+                .map(|element| element.id())
+                .collect(),
+        )
+    })
+}
+
+// The nodes that aren't inside another one of `nodes`, in document order, so
+// that walking each one's descendants visits every node once and in order.
+fn outermost(nodes: Vec<NodeRef<Node>>) -> Vec<NodeRef<Node>> {
+    let ids: HashSet<NodeId> = nodes.iter().map(|node| node.id()).collect();
+    let mut seen = HashSet::new();
+    let mut roots: Vec<NodeRef<Node>> = nodes
+        .into_iter()
+        .filter(|node| seen.insert(node.id()))
+        .filter(|node| !node.ancestors().any(|parent| ids.contains(&parent.id())))
+        .collect();
+    if roots.len() > 1 {
+        roots.sort_by_cached_key(position);
+    }
+    roots
+}
+
+// Sibling indices from the top of the tree down to `node`.
+fn position(node: &NodeRef<Node>) -> Vec<usize> {
+    let mut position: Vec<usize> = iter::once(*node)
+        .chain(node.ancestors())
+        .map(|node| node.prev_siblings().count())
+        .collect();
+    position.reverse();
+    position
+}
+
+// attr
+pub fn attr(env: &WasmEnv, descriptor: i32, key: u32, len: u32) -> i32 {
+    let key = match env.read_string(key, len) {
+        Ok(key) => key,
+        Err(_) => return -1,
+    };
+    let value = with_nodes(env, descriptor, |document, nodes| {
+        match key.strip_prefix("abs:") {
+            Some(key) => abs_url_of(document, &nodes, key),
+            None => attr_of(&nodes, &key).map(String::from),
+        }
+        .unwrap_or_default()
+    });
+    store_string(env, value)
+}
+
+pub fn abs_url(env: &WasmEnv, descriptor: i32, key: u32, len: u32) -> i32 {
+    let key = match env.read_string(key, len) {
+        Ok(key) => key,
+        Err(_) => return -1,
+    };
+    let value = with_nodes(env, descriptor, |document, nodes| {
+        abs_url_of(document, &nodes, &key).unwrap_or_default()
+    });
+    store_string(env, value)
+}
+
+// the value from the first node that has the attribute
+fn attr_of<'a>(nodes: &[NodeRef<'a, Node>], key: &str) -> Option<&'a str> {
+    nodes
+        .iter()
+        .filter_map(|node| node.value().as_element())
+        .find_map(|element| element.attr(key))
+}
+
+fn abs_url_of(document: &HtmlDocument, nodes: &[NodeRef<Node>], key: &str) -> Option<String> {
+    resolve(document.base_uri.as_deref(), attr_of(nodes, key)?)
+}
+
+// text
+pub fn text(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        nodes
+            .iter()
+            .map(|node| normalize(&node_text(*node, true)))
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    store_string(env, value)
+}
+pub fn untrimmed_text(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        nodes
+            .iter()
+            .map(|node| node_text(*node, false))
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    store_string(env, value)
+}
+pub fn own_text(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        let mut text = String::new();
+        for child in nodes.first().iter().flat_map(|node| node.children()) {
+            match child.value() {
+                Node::Text(str) => text.push_str(str),
+                Node::Element(element) if element.name() == "br" => text.push(' '),
+                _ => {}
+            }
+        }
+        normalize(&text)
+    });
+    store_string(env, value)
+}
+pub fn data(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        nodes
+            .iter()
+            .map(|node| {
+                node.descendants()
+                    .filter(|node| is_data(*node))
+                    .filter_map(|node| node.value().as_text().map(|str| str.to_string()))
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    store_string(env, value)
+}
+
+// script and style contents are data, not text
+fn is_data(node: NodeRef<Node>) -> bool {
+    matches!(
+        node.parent()
+            .and_then(|parent| parent.value().as_element().map(|element| element.name())),
+        Some("script" | "style")
+    )
+}
+
+// Text under `node`, with block elements and line breaks separated by spaces
+// when `spaced` is set, otherwise exactly as written.
+fn node_text(node: NodeRef<Node>, spaced: bool) -> String {
+    let mut text = String::new();
+    for edge in node.traverse() {
+        match edge {
+            Edge::Open(node) => match node.value() {
+                Node::Text(str) if !is_data(node) => text.push_str(str),
+                Node::Element(element) if element.name() == "br" => {
+                    text.push(if spaced { ' ' } else { '\n' })
+                }
+                Node::Element(element) if spaced && BLOCK_TAGS.contains(&element.name()) => {
+                    text.push(' ')
+                }
+                _ => {}
+            },
+            Edge::Close(node) => match node.value() {
+                Node::Element(element) if spaced && BLOCK_TAGS.contains(&element.name()) => {
+                    text.push(' ')
+                }
+                _ => {}
+            },
+        }
+    }
+    text
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// html
+pub fn html(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |document, nodes| {
+        nodes
+            .iter()
+            .map(|node| match ElementRef::wrap(*node) {
+                Some(element) => element.inner_html(),
+                None => document.html.html(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    store_string(env, value)
+}
+pub fn outer_html(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |document, nodes| {
+        nodes
+            .iter()
+            .map(|node| match ElementRef::wrap(*node) {
+                Some(element) => element.html(),
+                None => document.html.html(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    });
+    store_string(env, value)
+}
+
+// traversal
+pub fn first(env: &WasmEnv, descriptor: i32) -> i32 {
+    derive(env, descriptor, |_, nodes| Some(vec![nodes.first()?.id()]))
+}
+pub fn last(env: &WasmEnv, descriptor: i32) -> i32 {
+    derive(env, descriptor, |_, nodes| Some(vec![nodes.last()?.id()]))
+}
+pub fn next(env: &WasmEnv, descriptor: i32) -> i32 {
+    derive(env, descriptor, |_, nodes| {
+        let next = nodes
+            .first()?
+            .next_siblings()
+            .find(|node| node.value().is_element())?;
+        Some(vec![next.id()])
+    })
+}
+pub fn previous(env: &WasmEnv, descriptor: i32) -> i32 {
+    derive(env, descriptor, |_, nodes| {
+        let previous = nodes
+            .first()?
+            .prev_siblings()
+            .find(|node| node.value().is_element())?;
+        Some(vec![previous.id()])
+    })
+}
+pub fn body(env: &WasmEnv, descriptor: i32) -> i32 {
+    derive(env, descriptor, |document, _| {
+        let body = document
+            .html
+            .tree
+            .root()
+            .descendants()
+            .filter_map(ElementRef::wrap)
+            .find(|element| element.value().name() == "body")?;
+        Some(vec![body.id()])
+    })
+}
+pub fn array(env: &WasmEnv, descriptor: i32) -> i32 {
+    let node = match read_node(env, descriptor) {
+        Some(node) => node,
+        None => return -1,
+    };
+    let arr = node
+        .nodes
+        .iter()
+        .map(|id| {
+            WasmObject::Node(HtmlNode {
+                document: node.document.clone(),
+                nodes: vec![*id],
+            })
+        })
+        .collect();
+    env.store().store_value(WasmObject::Array(arr), None)
+}
+
+// node info
+pub fn base_uri(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |document, _| {
+        document.base_uri.clone().unwrap_or_default()
+    });
+    store_string(env, value)
+}
+pub fn id(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        first_element(&nodes)
+            .and_then(|element| element.value().id())
+            .unwrap_or_default()
+            .to_string()
+    });
+    store_string(env, value)
+}
+pub fn tag_name(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        first_element(&nodes)
+            .map(|element| element.value().name())
+            .unwrap_or_default()
+            .to_string()
+    });
+    store_string(env, value)
+}
+pub fn class_name(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = with_nodes(env, descriptor, |_, nodes| {
+        first_element(&nodes)
+            .and_then(|element| element.value().attr("class"))
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    });
+    store_string(env, value)
+}
+pub fn has_class(env: &WasmEnv, descriptor: i32, class_name: u32, len: u32) -> i32 {
+    let class_name = match env.read_string(class_name, len) {
+        Ok(class_name) => class_name,
+        Err(_) => return 0,
+    };
+    with_nodes(env, descriptor, |_, nodes| {
+        nodes
+            .iter()
+            .filter_map(|node| node.value().as_element())
+            .any(|element| element.has_class(&class_name, CaseSensitivity::AsciiCaseInsensitive))
+    })
+    .map_or(0, |has| has as i32)
+}
+pub fn has_attr(env: &WasmEnv, descriptor: i32, key: u32, len: u32) -> i32 {
+    let key = match env.read_string(key, len) {
+        Ok(key) => key,
+        Err(_) => return 0,
+    };
+    with_nodes(env, descriptor, |document, nodes| {
+        match key.strip_prefix("abs:") {
+            Some(key) => abs_url_of(document, &nodes, key).is_some(),
+            None => attr_of(&nodes, &key).is_some(),
+        }
+    })
+    .map_or(0, |has| has as i32)
+}
+
+fn first_element<'a>(nodes: &[NodeRef<'a, Node>]) -> Option<ElementRef<'a>> {
+    nodes.iter().find_map(|node| ElementRef::wrap(*node))
+}
+
+// escape
+pub fn escape(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = read_text(env, descriptor).map(|str| {
+        let mut escaped = String::with_capacity(str.len());
+        for char in str.chars() {
+            match char {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\u{a0}' => escaped.push_str("&nbsp;"),
+                _ => escaped.push(char),
+            }
+        }
+        escaped
+    });
+    store_string(env, value)
+}
+pub fn unescape(env: &WasmEnv, descriptor: i32) -> i32 {
+    let value = read_text(env, descriptor).map(|str| unescape_str(&str));
+    store_string(env, value)
+}
+
+// strings are used as is, nodes by their text
+fn read_text(env: &WasmEnv, descriptor: i32) -> Option<String> {
+    if let Some(WasmObject::String(str)) = env.store().peek_value(descriptor).as_deref() {
+        return Some(str.clone());
+    }
+    with_nodes(env, descriptor, |_, nodes| {
+        nodes
+            .iter()
+            .map(|node| normalize(&node_text(*node, true)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    })
+}
+
+pub fn unescape_str(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    let mut rest = str;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match decode_entity(&rest[1..]) {
+            Some((decoded, len)) => {
+                result.push_str(&decoded);
+                rest = &rest[1 + len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// Decodes the entity at the start of `str` (after the '&'), returning the
+// text and how many bytes it used.
+fn decode_entity(str: &str) -> Option<(String, usize)> {
+    if let Some(numeric) = str.strip_prefix('#') {
+        let (digits, radix, offset) = match numeric.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 2),
+            None => (numeric, 10, 1),
+        };
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        let code = u32::from_str_radix(&digits[..len], radix).ok()?;
+        let char = char::from_u32(code).unwrap_or('\u{fffd}');
+        let semicolon = digits[len..].starts_with(';') as usize;
+        return Some((char.to_string(), offset + len + semicolon));
+    }
+    let len = str
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(str.len());
+    if len == 0 {
+        return None;
+    }
+    let (codepoints, used) = if str[len..].starts_with(';') {
+        (NAMED_ENTITIES.get(&str[..len + 1])?, len + 1)
+    } else {
+        (NAMED_ENTITIES.get(&str[..len])?, len)
+    };
+    let decoded = [codepoints.0, codepoints.1]
+        .iter()
+        .filter(|code| **code != 0)
+        .filter_map(|code| char::from_u32(*code))
+        .collect();
+    Some((decoded, used))
+}
@@ -1,45 +1,70 @@
-use super::wasm::env::{WasmEnv, WasmGlobalStore, WasmObject};
-use serde_json::Value;
-use std::collections::HashMap;
+use super::wasm::charset;
+use super::wasm::env::{WasmEnv, WasmObject};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde_json::{Map, Number, Value};
 
 pub fn parse(env: &WasmEnv, data: u32, len: u32) -> i32 {
-    if len <= 0 {
+    if len == 0 {
         return -1;
     }
-    if let Ok(str) = env.read_string(data, len) {
-        parse_str(&mut env.store(), &str)
-    } else {
-        -1
+    let value = match env.read_bytes(data, len) {
+        Ok(data) => from_str(&charset::decode(&data, None)),
+        Err(_) => None,
+    };
+    match value {
+        Some(value) => env.store().store_json(value),
+        None => -1,
     }
 }
 
-pub fn parse_str(store: &mut WasmGlobalStore, str: &str) -> i32 {
-    let value: Value = serde_json::from_str(str).unwrap();
-    let obj = parse_value(&value);
-    store.store_value(obj, None)
+// Parsed without the store, which other imports can use in the meantime.
+pub fn from_str(str: &str) -> Option<Value> {
+    match serde_json::from_str::<Value>(str) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            // usually an html error page where json was expected
+            let start: String = str.trim_start().chars().take(80).collect();
+            log::warn!("json parse failed: {} (input starts with {:?})", err, start);
+            None
+        }
+    }
 }
 
-fn parse_value(value: &Value) -> WasmObject {
-    match value {
-        arr if arr.is_array() => WasmObject::Array(
-            arr.as_array()
-                .unwrap()
-                .into_iter()
-                .map(|v| parse_value(v))
-                .collect(),
-        ),
-        obj if obj.is_object() => WasmObject::Object(
-            obj.as_object()
-                .unwrap()
-                .into_iter()
-                .map(|m| (m.0.clone(), parse_value(m.1)))
-                .collect::<HashMap<String, WasmObject>>(),
+pub fn stringify(env: &WasmEnv, descriptor: i32) -> i32 {
+    let mut store = env.store();
+    let json = match store.json_value(descriptor) {
+        Some(value) => value.to_string(),
+        None => match store.read_value(descriptor) {
+            Some(obj) => to_value(&obj).to_string(),
+            None => return -1,
+        },
+    };
+    store.store_value(WasmObject::String(json), None)
+}
+
+// Dates become ISO 8601 strings, like JSON.stringify does with them, and
+// values with no JSON counterpart become null.
+pub fn to_value(obj: &WasmObject) -> Value {
+    match obj {
+        WasmObject::Int(int) => Value::from(*int),
+        WasmObject::Float(float) => Number::from_f64(*float).map_or(Value::Null, Value::Number),
+        WasmObject::String(str) => Value::from(str.as_str()),
+        WasmObject::Bool(bool) => Value::from(*bool),
+        WasmObject::Array(arr) => Value::Array(arr.iter().map(to_value).collect()),
+        WasmObject::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), to_value(value)))
+                .collect::<Map<String, Value>>(),
         ),
-        int if int.is_i64() => WasmObject::Int(int.as_i64().unwrap()),
-        float if float.is_f64() => WasmObject::Float(float.as_f64().unwrap()),
-        str if str.is_string() => WasmObject::String(str.as_str().unwrap().to_string()),
-        bool if bool.is_boolean() => WasmObject::Bool(bool.as_bool().unwrap()),
-        null if null.is_null() => WasmObject::Null,
-        _ => WasmObject::Null,
+        WasmObject::Date(date) => {
+            let secs = date.floor();
+            let nanos = ((date - secs) * 1e9) as u32;
+            Utc.timestamp_opt(secs as i64, nanos)
+                .single()
+                .map_or(Value::Null, |date| {
+                    Value::from(date.to_rfc3339_opts(SecondsFormat::Millis, true))
+                })
+        }
+        _ => Value::Null,
     }
 }
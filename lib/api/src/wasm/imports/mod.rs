@@ -5,6 +5,7 @@ use wasmer::{imports, Function, ImportObject, Store};
 pub mod aidoku;
 pub mod defaults;
 pub mod env;
+pub mod html;
 pub mod json;
 pub mod net;
 pub mod std;
@@ -12,60 +13,100 @@ pub mod std;
 pub fn generate_imports(store: &Store, env: &WasmEnv) -> ImportObject {
     imports! {
         "env" => {
-            "abort" => Function::new_native(&store, env::abort),
-            "print" => Function::new_native_with_env(&store, env.clone(), env::print),
+            "abort" => Function::new_native(store, env::abort),
+            "print" => Function::new_native_with_env(store, env.named("env.print"), env::print),
         },
         "std" => {
-            "copy" => Function::new_native_with_env(&store, env.clone(), std::copy),
-            "destroy" => Function::new_native_with_env(&store, env.clone(), std::destroy),
-            "typeof" => Function::new_native_with_env(&store, env.clone(), std::value_kind),
-            "create_null" => Function::new_native_with_env(&store, env.clone(), std::create_null),
-            "create_int" => Function::new_native_with_env(&store, env.clone(), std::create_int),
-            "create_float" => Function::new_native_with_env(&store, env.clone(), std::create_float),
-            "create_bool" => Function::new_native_with_env(&store, env.clone(), std::create_bool),
-            "create_string" => Function::new_native_with_env(&store, env.clone(), std::create_string),
-            "create_object" => Function::new_native_with_env(&store, env.clone(), std::create_object),
-            "create_array" => Function::new_native_with_env(&store, env.clone(), std::create_array),
-            "create_date" => Function::new_native_with_env(&store, env.clone(), std::create_date),
-            "string_len" => Function::new_native_with_env(&store, env.clone(), std::string_len),
-            "read_string" => Function::new_native_with_env(&store, env.clone(), std::read_string),
-            "read_int" => Function::new_native_with_env(&store, env.clone(), std::read_int),
-            "read_float" => Function::new_native_with_env(&store, env.clone(), std::read_float),
-            "read_bool" => Function::new_native_with_env(&store, env.clone(), std::read_bool),
-            "read_date" => Function::new_native_with_env(&store, env.clone(), std::read_date),
-            "read_date_string" => Function::new_native_with_env(&store, env.clone(), std::read_date_string),
-            "object_len" => Function::new_native_with_env(&store, env.clone(), std::object_len),
-            "object_get" => Function::new_native_with_env(&store, env.clone(), std::object_get),
-            "object_set" => Function::new_native_with_env(&store, env.clone(), std::object_set),
-            "object_values" => Function::new_native_with_env(&store, env.clone(), std::object_values),
-            "array_len" => Function::new_native_with_env(&store, env.clone(), std::array_len),
-            "array_get" => Function::new_native_with_env(&store, env.clone(), std::array_get),
-            "array_append" => Function::new_native_with_env(&store, env.clone(), std::array_append),
+            "copy" => Function::new_native_with_env(store, env.named("std.copy"), std::copy),
+            "destroy" => Function::new_native_with_env(store, env.named("std.destroy"), std::destroy),
+            "typeof" => Function::new_native_with_env(store, env.named("std.typeof"), std::value_kind),
+            "create_null" => Function::new_native_with_env(store, env.named("std.create_null"), std::create_null),
+            "create_int" => Function::new_native_with_env(store, env.named("std.create_int"), std::create_int),
+            "create_float" => Function::new_native_with_env(store, env.named("std.create_float"), std::create_float),
+            "create_bool" => Function::new_native_with_env(store, env.named("std.create_bool"), std::create_bool),
+            "create_string" => Function::new_native_with_env(store, env.named("std.create_string"), std::create_string),
+            "create_object" => Function::new_native_with_env(store, env.named("std.create_object"), std::create_object),
+            "create_array" => Function::new_native_with_env(store, env.named("std.create_array"), std::create_array),
+            "create_date" => Function::new_native_with_env(store, env.named("std.create_date"), std::create_date),
+            "string_len" => Function::new_native_with_env(store, env.named("std.string_len"), std::string_len),
+            "read_string" => Function::new_native_with_env(store, env.named("std.read_string"), std::read_string),
+            "read_int" => Function::new_native_with_env(store, env.named("std.read_int"), std::read_int),
+            "read_float" => Function::new_native_with_env(store, env.named("std.read_float"), std::read_float),
+            "read_bool" => Function::new_native_with_env(store, env.named("std.read_bool"), std::read_bool),
+            "read_date" => Function::new_native_with_env(store, env.named("std.read_date"), std::read_date),
+            "read_date_string" => Function::new_native_with_env(store, env.named("std.read_date_string"), std::read_date_string),
+            "object_len" => Function::new_native_with_env(store, env.named("std.object_len"), std::object_len),
+            "object_get" => Function::new_native_with_env(store, env.named("std.object_get"), std::object_get),
+            "object_set" => Function::new_native_with_env(store, env.named("std.object_set"), std::object_set),
+            "object_remove" => Function::new_native_with_env(store, env.named("std.object_remove"), std::object_remove),
+            "object_keys" => Function::new_native_with_env(store, env.named("std.object_keys"), std::object_keys),
+            "object_values" => Function::new_native_with_env(store, env.named("std.object_values"), std::object_values),
+            "array_len" => Function::new_native_with_env(store, env.named("std.array_len"), std::array_len),
+            "array_get" => Function::new_native_with_env(store, env.named("std.array_get"), std::array_get),
+            "array_set" => Function::new_native_with_env(store, env.named("std.array_set"), std::array_set),
+            "array_append" => Function::new_native_with_env(store, env.named("std.array_append"), std::array_append),
+            "array_remove" => Function::new_native_with_env(store, env.named("std.array_remove"), std::array_remove),
          },
          "aidoku" => {
-             "create_manga" => Function::new_native_with_env(&store, env.clone(), aidoku::create_manga),
-             "create_manga_result" => Function::new_native_with_env(&store, env.clone(), aidoku::create_manga_result),
-             "create_chapter" => Function::new_native_with_env(&store, env.clone(), aidoku::create_chapter),
-             "create_page" => Function::new_native_with_env(&store, env.clone(), aidoku::create_page),
-             "create_deeplink" => Function::new_native_with_env(&store, env.clone(), aidoku::create_deeplink),
+             "create_manga" => Function::new_native_with_env(store, env.named("aidoku.create_manga"), aidoku::create_manga),
+             "create_manga_result" => Function::new_native_with_env(store, env.named("aidoku.create_manga_result"), aidoku::create_manga_result),
+             "create_chapter" => Function::new_native_with_env(store, env.named("aidoku.create_chapter"), aidoku::create_chapter),
+             "create_page" => Function::new_native_with_env(store, env.named("aidoku.create_page"), aidoku::create_page),
+             "create_deeplink" => Function::new_native_with_env(store, env.named("aidoku.create_deeplink"), aidoku::create_deeplink),
          },
          "net" => {
-             "init" => Function::new_native_with_env(&store, env.clone(), net::init),
-             "close" => Function::new_native_with_env(&store, env.clone(), net::close),
-             "send" => Function::new_native_with_env(&store, env.clone(), net::send),
-             "set_url" => Function::new_native_with_env(&store, env.clone(), net::set_url),
-             "set_header" => Function::new_native_with_env(&store, env.clone(), net::set_header),
-             "set_body" => Function::new_native_with_env(&store, env.clone(), net::set_body),
-             "get_data" => Function::new_native_with_env(&store, env.clone(), net::get_data),
-             "get_data_size" => Function::new_native_with_env(&store, env.clone(), net::get_data_size),
-             "json" => Function::new_native_with_env(&store, env.clone(), net::json),
+             "init" => Function::new_native_with_env(store, env.named("net.init"), net::init),
+             "close" => Function::new_native_with_env(store, env.named("net.close"), net::close),
+             "send" => Function::new_native_with_env(store, env.named("net.send"), net::send),
+             "set_url" => Function::new_native_with_env(store, env.named("net.set_url"), net::set_url),
+             "set_header" => Function::new_native_with_env(store, env.named("net.set_header"), net::set_header),
+             "set_body" => Function::new_native_with_env(store, env.named("net.set_body"), net::set_body),
+             "get_data" => Function::new_native_with_env(store, env.named("net.get_data"), net::get_data),
+             "get_data_size" => Function::new_native_with_env(store, env.named("net.get_data_size"), net::get_data_size),
+             "json" => Function::new_native_with_env(store, env.named("net.json"), net::json),
+             "html" => Function::new_native_with_env(store, env.named("net.html"), net::html),
+             "get_status_code" => Function::new_native_with_env(store, env.named("net.get_status_code"), net::get_status_code),
+             "get_header" => Function::new_native_with_env(store, env.named("net.get_header"), net::get_header),
+             "get_image" => Function::new_native_with_env(store, env.named("net.get_image"), net::get_image),
+             "set_rate_limit" => Function::new_native_with_env(store, env.named("net.set_rate_limit"), net::set_rate_limit),
+             "set_rate_limit_period" => Function::new_native_with_env(store, env.named("net.set_rate_limit_period"), net::set_rate_limit_period),
+         },
+         "html" => {
+             "parse" => Function::new_native_with_env(store, env.named("html.parse"), html::parse),
+             "parse_with_uri" => Function::new_native_with_env(store, env.named("html.parse_with_uri"), html::parse_with_uri),
+             "parse_fragment" => Function::new_native_with_env(store, env.named("html.parse_fragment"), html::parse_fragment),
+             "parse_fragment_with_uri" => Function::new_native_with_env(store, env.named("html.parse_fragment_with_uri"), html::parse_fragment_with_uri),
+             "select" => Function::new_native_with_env(store, env.named("html.select"), html::select),
+             "attr" => Function::new_native_with_env(store, env.named("html.attr"), html::attr),
+             "abs_url" => Function::new_native_with_env(store, env.named("html.abs_url"), html::abs_url),
+             "text" => Function::new_native_with_env(store, env.named("html.text"), html::text),
+             "untrimmed_text" => Function::new_native_with_env(store, env.named("html.untrimmed_text"), html::untrimmed_text),
+             "own_text" => Function::new_native_with_env(store, env.named("html.own_text"), html::own_text),
+             "data" => Function::new_native_with_env(store, env.named("html.data"), html::data),
+             "html" => Function::new_native_with_env(store, env.named("html.html"), html::html),
+             "outer_html" => Function::new_native_with_env(store, env.named("html.outer_html"), html::outer_html),
+             "first" => Function::new_native_with_env(store, env.named("html.first"), html::first),
+             "last" => Function::new_native_with_env(store, env.named("html.last"), html::last),
+             "next" => Function::new_native_with_env(store, env.named("html.next"), html::next),
+             "previous" => Function::new_native_with_env(store, env.named("html.previous"), html::previous),
+             "body" => Function::new_native_with_env(store, env.named("html.body"), html::body),
+             "array" => Function::new_native_with_env(store, env.named("html.array"), html::array),
+             "base_uri" => Function::new_native_with_env(store, env.named("html.base_uri"), html::base_uri),
+             "id" => Function::new_native_with_env(store, env.named("html.id"), html::id),
+             "tag_name" => Function::new_native_with_env(store, env.named("html.tag_name"), html::tag_name),
+             "class_name" => Function::new_native_with_env(store, env.named("html.class_name"), html::class_name),
+             "has_class" => Function::new_native_with_env(store, env.named("html.has_class"), html::has_class),
+             "has_attr" => Function::new_native_with_env(store, env.named("html.has_attr"), html::has_attr),
+             "escape" => Function::new_native_with_env(store, env.named("html.escape"), html::escape),
+             "unescape" => Function::new_native_with_env(store, env.named("html.unescape"), html::unescape),
          },
          "json" => {
-             "parse" => Function::new_native_with_env(&store, env.clone(), json::parse),
+             "parse" => Function::new_native_with_env(store, env.named("json.parse"), json::parse),
+             "stringify" => Function::new_native_with_env(store, env.named("json.stringify"), json::stringify),
          },
          "defaults" => {
-             "get" => Function::new_native_with_env(&store, env.clone(), defaults::get),
-             "set" => Function::new_native_with_env(&store, env.clone(), defaults::set),
+             "get" => Function::new_native_with_env(store, env.named("defaults.get"), defaults::get),
+             "set" => Function::new_native_with_env(store, env.named("defaults.set"), defaults::set),
          }
     }
 }
@@ -1,8 +1,11 @@
-use super::json;
-use super::wasm::env::{HttpMethod, Response, WasmEnv};
-use bytes::Bytes;
-use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
-use std::str::FromStr;
+use super::wasm::env::{HttpMethod, Request, Response, WasmEnv, WasmObject};
+use super::wasm::interceptor::{detect_challenge, Action, Exchange};
+use super::wasm::transport::{BodyTooLarge, NetError, NetErrorKind};
+use super::{html, json};
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use url::Url;
 
 pub fn init(env: &WasmEnv, method: i32) -> i32 {
     env.store().new_request(HttpMethod::from(method))
@@ -13,51 +16,277 @@ pub fn close(env: &WasmEnv, descriptor: i32) {
 }
 
 pub fn send(env: &WasmEnv, descriptor: i32) {
-    let mut store = env.store();
-    let mut req = match store.get_request(&descriptor) {
+    // the store stays unlocked while the request is in flight
+    let req = match env.store().get_request(&descriptor) {
         Some(x) => x.clone(),
         _ => return,
     };
-    if let Some(url) = req.url.clone() {
-        let res = {
-            let client = reqwest::blocking::Client::new();
-            let mut headers = HeaderMap::new();
-            req.headers.clone().into_iter().for_each(|m| {
-                if let Ok(name) = HeaderName::from_str(&m.0) {
-                    if let Ok(value) = HeaderValue::from_str(&m.1.unwrap_or_default()) {
-                        headers.insert(name, value);
-                    }
+    if req.url.is_some() {
+        let result = fetch(env, &req, None);
+        let mut store = env.store();
+        if let Some(mut req) = store.get_request(&descriptor).cloned() {
+            // like on iOS, a failed request has no response, so its status is -1
+            match result {
+                Ok(response) => {
+                    req.response = Some(response);
+                    req.error = None;
+                }
+                Err(err) => {
+                    req.response = None;
+                    req.error = Some(err);
+                }
+            }
+            store.set_request(descriptor, req);
+        }
+    }
+}
+
+// Why the request's last send failed, if it did.
+pub fn error(env: &WasmEnv, descriptor: i32) -> Option<NetError> {
+    env.store().get_request(&descriptor)?.error.clone()
+}
+
+// Sends `req` for the host the way `send` would, but streams a successful
+// response's body into `body` rather than keeping it in memory.
+pub fn download(env: &WasmEnv, req: &Request, body: &mut dyn Write) -> Result<Response, NetError> {
+    let mut sink = Sink {
+        body,
+        written: 0,
+        limit: env.network.max_body_size,
+    };
+    fetch(env, req, Some(&mut sink))
+}
+
+// Where a download's body goes. Once anything has been written the request
+// can't be sent again, or the body would be written twice.
+struct Sink<'a> {
+    body: &'a mut dyn Write,
+    written: usize,
+    // enforced here too, so it holds for any transport as the body streams
+    limit: Option<usize>,
+}
+
+impl Write for Sink<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(limit) = self.limit {
+            if self.written + buf.len() > limit {
+                return Err(BodyTooLarge { limit }.into());
+            }
+        }
+        let written = self.body.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.body.flush()
+    }
+}
+
+// Redirects are followed here rather than by the transport so cookies set
+// along the way end up in the source's jar.
+fn fetch(env: &WasmEnv, req: &Request, mut sink: Option<&mut Sink>) -> Result<Response, NetError> {
+    let url = req.url.clone().unwrap_or_default();
+    let mut url = Url::parse(&url).map_err(|err| {
+        log::warn!("request to {} failed: {}", url, err);
+        NetError::new(NetErrorKind::InvalidUrl, err.to_string())
+    })?;
+    let mut req = Request {
+        response: None,
+        error: None,
+        ..req.clone()
+    };
+
+    let mut redirects = 0;
+    loop {
+        let res = send_hop(env, &req, &url, sink.as_deref_mut())?;
+
+        let location = res
+            .header_values("location")
+            .next()
+            .and_then(|location| url.join(location).ok());
+        match location {
+            Some(next)
+                if (300..400).contains(&res.status_code)
+                    && redirects < env.network.max_redirects() =>
+            {
+                redirects += 1;
+                let status = res.status_code;
+                if status == 303 || (matches!(status, 301 | 302) && req.method == HttpMethod::Post)
+                {
+                    req.method = HttpMethod::Get;
+                    req.body = None;
+                    req.remove_header("content-type");
+                    req.remove_header("content-length");
                 }
-            });
-            let mut builder = match req.method {
-                HttpMethod::Get => client.get(url),
-                HttpMethod::Post => client.post(url),
-                HttpMethod::Head => client.head(url),
-                HttpMethod::Put => client.put(url),
-                HttpMethod::Delete => client.delete(url),
+                if next.host_str() != url.host_str() {
+                    req.remove_header("authorization");
+                    req.remove_header("cookie");
+                }
+                url = next;
             }
-            .headers(headers);
-            if let Some(body) = req.body.clone() {
-                builder = builder.body(body);
+            _ => return Ok(res),
+        }
+    }
+}
+
+const MAX_INTERCEPTS: usize = 3;
+
+// Sends the request to `url` with the jar's cookies, letting the interceptor
+// look at it and send it again once a challenge is solved.
+fn send_hop(
+    env: &WasmEnv,
+    req: &Request,
+    url: &Url,
+    mut sink: Option<&mut Sink>,
+) -> Result<Response, NetError> {
+    let addresses = env.allowlist.resolve(url).map_err(|reason| {
+        log::warn!("request to {} blocked: {}", url, reason);
+        NetError::new(NetErrorKind::Blocked, reason)
+    })?;
+    let mut intercepts = 0;
+    loop {
+        let mut sent = req.clone();
+        sent.url = Some(url.to_string());
+        sent.addresses = addresses.clone();
+        if let Some(cookies) = env.cookies.header(url) {
+            // cookies the source set itself go first
+            let cookies = match req.header("cookie") {
+                Some(existing) => format!("{}; {}", existing, cookies),
+                None => cookies,
+            };
+            sent.set_header("Cookie", cookies);
+        }
+        if let Some(interceptor) = &env.interceptor {
+            interceptor.request(&mut sent);
+        }
+        let res = send_with_retries(env, &sent, sink.as_deref_mut())?;
+        // transports hand back whole bodies, so this only catches an oversized
+        // one after it was read; ReqwestTransport stops reading at the limit
+        // itself, and downloads are cut off by the sink as they stream
+        if let Some(limit) = env.network.max_body_size {
+            if res.data.len() > limit {
+                let err = NetError::from_error(&BodyTooLarge { limit }.into());
+                log::warn!("request to {} failed: {}", url, err);
+                return Err(err);
             }
-            builder.send()
+        }
+
+        env.cookies
+            .set_cookies(url, res.header_values("set-cookie"));
+
+        let challenge = detect_challenge(&res);
+        let action = match &env.interceptor {
+            Some(interceptor) => interceptor.response(&Exchange {
+                request: &sent,
+                response: &res,
+                challenge,
+                cookies: &env.cookies,
+            }),
+            None => Action::Continue,
         };
-        let response = if let Ok(res) = res {
-            Response {
-                status_code: res.status().as_u16() as i32,
-                data: res.bytes().unwrap_or(Bytes::new()).to_vec(),
+        if let Some(challenge) = challenge {
+            log::info!("{:?} challenge at {}", challenge, url);
+        }
+        let streamed = sink.as_ref().is_some_and(|sink| sink.written > 0);
+        match action {
+            Action::Retry if intercepts < MAX_INTERCEPTS && !streamed => intercepts += 1,
+            _ => return Ok(res),
+        }
+    }
+}
+
+// Sends one hop, trying again after transient failures as the retry policy allows.
+fn send_with_retries(
+    env: &WasmEnv,
+    req: &Request,
+    mut sink: Option<&mut Sink>,
+) -> Result<Response, NetError> {
+    let policy = &env.network.retry;
+    let url = req.url.clone().unwrap_or_default();
+    let retryable = policy.retry_non_idempotent || req.method != HttpMethod::Post;
+    let mut attempt = 1;
+    loop {
+        // answers that don't need the network don't wait for a slot
+        let body = sink.as_deref_mut().map(|sink| sink as &mut dyn Write);
+        let result = env
+            .transport
+            .exchange(req, body, &|| env.rate_limiter.acquire())
+            .map_err(|err| NetError::from_error(&err));
+        let streamed = sink.as_ref().is_some_and(|sink| sink.written > 0);
+        let retry_after = match &result {
+            // challenges are left to the interceptor
+            Ok(res)
+                if policy.statuses.contains(&res.status_code)
+                    && detect_challenge(res).is_none() =>
+            {
+                Some(
+                    res.header("retry-after")
+                        .as_deref()
+                        .and_then(parse_retry_after),
+                )
             }
-        } else {
-            Response {
-                status_code: 400,
-                data: Vec::new(),
+            Ok(_) => None,
+            // anything else won't go away by asking again
+            Err(err) if err.kind.is_network() => Some(None),
+            Err(_) => None,
+        };
+        let delay = match retry_after {
+            Some(retry_after) if retryable && !streamed && attempt < policy.max_attempts => {
+                policy.delay(attempt, retry_after)
             }
+            _ => None,
         };
-        req.response = Some(response);
-        store.set_request(descriptor, req);
+        match delay {
+            Some(delay) => {
+                match &result {
+                    Ok(res) => log::debug!(
+                        "request to {} returned {}, retrying in {:?}",
+                        url,
+                        res.status_code,
+                        delay
+                    ),
+                    Err(err) => log::debug!(
+                        "request to {} failed: {}, retrying in {:?}",
+                        url,
+                        err,
+                        delay
+                    ),
+                }
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            None => {
+                if let Err(err) = &result {
+                    log::warn!("request to {} failed: {}", url, err);
+                }
+                return result;
+            }
+        }
     }
 }
 
+// Retry-After is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+pub fn set_rate_limit(env: &WasmEnv, limit: i32) {
+    env.rate_limiter.set_limit(limit);
+}
+
+pub fn set_rate_limit_period(env: &WasmEnv, period: i32) {
+    env.rate_limiter.set_period(period);
+}
+
 pub fn set_url(env: &WasmEnv, descriptor: i32, value: u32, len: u32) {
     if let Ok(url) = env.read_string(value, len) {
         let mut store = env.store();
@@ -109,7 +338,7 @@ pub fn get_data_size(env: &WasmEnv, descriptor: i32) -> i32 {
         Some(x) => x,
         _ => return -1,
     };
-    if let Some(res) = req.response.clone() {
+    if let Some(res) = &req.response {
         res.data.len() as i32
     } else {
         -1
@@ -118,28 +347,74 @@ pub fn get_data_size(env: &WasmEnv, descriptor: i32) -> i32 {
 
 pub fn get_data(env: &WasmEnv, descriptor: i32, buff: i32, size: i32) {
     // println!("get_data({}, {}, {})", descriptor, buff, size);
-    if let Some(req) = env.store().get_request(&descriptor) {
-        if let Some(res) = req.response.clone() {
-            let size = size as usize;
-            let data_buff = if size < res.data.len() {
-                &res.data[..size as usize]
-            } else {
-                res.data.as_slice()
-            };
-            env.write_bytes(data_buff, buff as u32);
-        }
+    let data = env
+        .store()
+        .get_request(&descriptor)
+        .and_then(|req| req.response.as_ref())
+        .map(|res| res.data[..res.data.len().min(size as usize)].to_vec());
+    if let Some(data) = data {
+        env.write_bytes(&data, buff as u32);
     }
 }
 
+// The response and the url it came from, copied out so the store isn't held
+// while the body is decoded and parsed.
+fn response(env: &WasmEnv, descriptor: i32) -> Option<(Response, Option<String>)> {
+    let store = env.store();
+    let req = store.get_request(&descriptor)?;
+    let res = req.response.clone()?;
+    let url = res.url.clone().or_else(|| req.url.clone());
+    Some((res, url))
+}
+
 pub fn json(env: &WasmEnv, descriptor: i32) -> i32 {
-    let mut store = env.store();
-    if let Some(req) = store.get_request(&descriptor) {
-        if let Some(res) = req.response.clone() {
-            json::parse_str(&mut store, &String::from_utf8_lossy(&res.data))
-        } else {
-            -1
+    match response(env, descriptor).and_then(|(res, _)| json::from_str(&res.text())) {
+        Some(value) => env.store().store_json(value),
+        None => -1,
+    }
+}
+
+pub fn html(env: &WasmEnv, descriptor: i32) -> i32 {
+    match response(env, descriptor) {
+        Some((res, base_uri)) => {
+            let document = html::parse_document(&res.text(), base_uri);
+            env.store().store_value(document, None)
         }
-    } else {
-        -1
+        None => -1,
+    }
+}
+
+pub fn get_status_code(env: &WasmEnv, descriptor: i32) -> i32 {
+    match env.store().get_request(&descriptor) {
+        Some(req) => req.response.as_ref().map_or(-1, |res| res.status_code),
+        None => -1,
+    }
+}
+
+pub fn get_header(env: &WasmEnv, descriptor: i32, name: u32, len: u32) -> i32 {
+    let name = match env.read_string(name, len) {
+        Ok(name) => name,
+        Err(_) => return -1,
+    };
+    let mut store = env.store();
+    let value = store
+        .get_request(&descriptor)
+        .and_then(|req| req.response.as_ref())
+        .and_then(|res| res.header(&name));
+    match value {
+        Some(value) => store.store_value(WasmObject::String(value), None),
+        None => -1,
+    }
+}
+
+pub fn get_image(env: &WasmEnv, descriptor: i32) -> i32 {
+    let mut store = env.store();
+    let data = store
+        .get_request(&descriptor)
+        .and_then(|req| req.response.as_ref())
+        .map(|res| res.data.clone());
+    match data {
+        Some(data) if !data.is_empty() => store.store_value(WasmObject::Image(data), None),
+        _ => -1,
     }
 }
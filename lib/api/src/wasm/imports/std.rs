@@ -1,13 +1,20 @@
-use super::wasm::env::{WasmEnv, WasmObject};
+use super::wasm::date;
+use super::wasm::env::{ValueKey, WasmEnv, WasmObject};
 use super::wasm::models::KVC;
-use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
-use std::collections::HashMap;
+use chrono::Utc;
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::borrow::Cow;
 
 // copy
 pub fn copy(env: &WasmEnv, descriptor: i32) -> i32 {
     // println!("copy({})", descriptor);
     let mut store = env.store();
-    if let Some(obj) = store.read_value(descriptor).cloned() {
+    // json stays unconverted in the copy too
+    if let Some(value) = store.json_value(descriptor).cloned() {
+        return store.store_json(value);
+    }
+    if let Some(obj) = store.read_value(descriptor).map(Cow::into_owned) {
         store.store_value(obj, None)
     } else {
         -1
@@ -32,13 +39,17 @@ pub fn create_float(env: &WasmEnv, value: f64) -> i32 {
 pub fn create_bool(env: &WasmEnv, value: i32) -> i32 {
     env.store().store_value(WasmObject::Bool(value != 0), None)
 }
-pub fn create_string(env: &WasmEnv) -> i32 {
-    env.store()
-        .store_value(WasmObject::String(String::new()), None)
+pub fn create_string(env: &WasmEnv, value: u32, len: u32) -> i32 {
+    let str = if len > 0 {
+        env.read_string(value, len).unwrap_or_default()
+    } else {
+        String::new()
+    };
+    env.store().store_value(WasmObject::String(str), None)
 }
 pub fn create_object(env: &WasmEnv) -> i32 {
     env.store()
-        .store_value(WasmObject::Object(HashMap::new()), None)
+        .store_value(WasmObject::Object(IndexMap::new()), None)
 }
 pub fn create_array(env: &WasmEnv) -> i32 {
     env.store().store_value(WasmObject::Array(Vec::new()), None)
@@ -57,21 +68,34 @@ pub fn create_date(env: &WasmEnv, value: f64) -> i32 {
 // typeof
 pub fn value_kind(env: &WasmEnv, descriptor: i32) -> i32 {
     // println!("typeof({})", descriptor);
-    if let Some(obj) = env.store().read_value(descriptor) {
+    if let Some(obj) = env.store().peek_value(descriptor) {
         obj.kind()
     } else {
         WasmObject::Null.kind()
     }
 }
 
+// numbers and bools are readable as strings, like on iOS
+fn string_value(obj: &WasmObject) -> Option<String> {
+    match obj {
+        WasmObject::String(str) => Some(str.clone()),
+        WasmObject::Int(int) => Some(int.to_string()),
+        WasmObject::Float(float) => Some(format!("{:?}", float)),
+        WasmObject::Bool(bool) => Some(bool.to_string()),
+        _ => None,
+    }
+}
+
 // string_len
 pub fn string_len(env: &WasmEnv, descriptor: i32) -> i32 {
     // println!("string_len({})", descriptor);
-    if let Some(obj) = env.store().read_value(descriptor) {
-        match obj {
-            WasmObject::String(str) => str.len() as i32,
-            _ => 0,
-        }
+    if let Some(str) = env
+        .store()
+        .peek_value(descriptor)
+        .as_deref()
+        .and_then(string_value)
+    {
+        str.len() as i32
     } else {
         0
     }
@@ -79,28 +103,38 @@ pub fn string_len(env: &WasmEnv, descriptor: i32) -> i32 {
 // read_*
 pub fn read_string(env: &WasmEnv, descriptor: i32, buff: i32, size: i32) {
     // println!("read_string({}, {}, {})", descriptor, buff, size);
-    if let Some(obj) = env.store().read_value(descriptor) {
-        match obj {
-            WasmObject::String(str) => {
-                let size = size as usize;
-                let str_buff = if size < str.len() {
-                    str[..size as usize].as_bytes()
-                } else {
-                    str.as_bytes()
-                };
-                env.write_bytes(str_buff, buff as u32);
-            }
-            _ => (),
+    if let Some(str) = env
+        .store()
+        .peek_value(descriptor)
+        .as_deref()
+        .and_then(string_value)
+    {
+        let size = size as usize;
+        let str_buff = if size < str.len() {
+            &str.as_bytes()[..size]
+        } else {
+            str.as_bytes()
         };
+        env.write_bytes(str_buff, buff as u32);
     }
 }
 pub fn read_int(env: &WasmEnv, descriptor: i32) -> i64 {
     // println!("read_int({})", descriptor);
-    if let Some(obj) = env.store().read_value(descriptor).cloned() {
+    if let Some(obj) = env.store().peek_value(descriptor).map(Cow::into_owned) {
         match obj {
             WasmObject::Int(int) => int,
             WasmObject::Float(float) => float as i64,
             WasmObject::Bool(bool) => bool as i64,
+            WasmObject::String(str) => {
+                let str = str.trim();
+                if let Ok(int) = str.parse::<i64>() {
+                    int
+                } else if let Ok(float) = str.parse::<f64>() {
+                    float as i64
+                } else {
+                    -1
+                }
+            }
             _ => -1,
         }
     } else {
@@ -108,10 +142,12 @@ pub fn read_int(env: &WasmEnv, descriptor: i32) -> i64 {
     }
 }
 pub fn read_float(env: &WasmEnv, descriptor: i32) -> f64 {
-    if let Some(obj) = env.store().read_value(descriptor).cloned() {
+    if let Some(obj) = env.store().peek_value(descriptor).map(Cow::into_owned) {
         match obj {
             WasmObject::Float(float) => float,
             WasmObject::Int(int) => int as f64,
+            WasmObject::Bool(bool) => bool as i64 as f64,
+            WasmObject::String(str) => str.trim().parse::<f64>().unwrap_or(-1f64),
             _ => -1f64,
         }
     } else {
@@ -119,16 +155,12 @@ pub fn read_float(env: &WasmEnv, descriptor: i32) -> f64 {
     }
 }
 pub fn read_bool(env: &WasmEnv, descriptor: i32) -> i32 {
-    if let Some(obj) = env.store().read_value(descriptor).cloned() {
+    if let Some(obj) = env.store().peek_value(descriptor).map(Cow::into_owned) {
         match obj {
             WasmObject::Bool(bool) => bool as i32,
-            WasmObject::Int(int) => {
-                if int == 0 {
-                    0
-                } else {
-                    1
-                }
-            }
+            WasmObject::Int(int) => (int != 0) as i32,
+            WasmObject::Float(float) => (float != 0f64) as i32,
+            WasmObject::String(str) => matches!(str.trim(), "true" | "1") as i32,
             _ => 0,
         }
     } else {
@@ -136,10 +168,11 @@ pub fn read_bool(env: &WasmEnv, descriptor: i32) -> i32 {
     }
 }
 pub fn read_date(env: &WasmEnv, descriptor: i32) -> f64 {
-    if let Some(obj) = env.store().read_value(descriptor).cloned() {
+    if let Some(obj) = env.store().peek_value(descriptor).map(Cow::into_owned) {
         match obj {
             WasmObject::Date(date) => date,
             WasmObject::Float(float) => float,
+            WasmObject::Int(int) => int as f64,
             _ => -1f64,
         }
     } else {
@@ -147,6 +180,7 @@ pub fn read_date(env: &WasmEnv, descriptor: i32) -> f64 {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn read_date_string(
     env: &WasmEnv,
     descriptor: i32,
@@ -157,31 +191,11 @@ pub fn read_date_string(
     timezone: u32,
     timezone_len: u32,
 ) -> f64 {
-    if let Some(WasmObject::String(str)) = env.store().read_value(descriptor).cloned() {
-        let format = env
-            .read_string(format, format_len)
-            .unwrap_or_default()
-            .replace("yyyy", "%Y")
-            .replace("MM", "%m")
-            .replace("dd", "%d")
-            .replace("d", "%d")
-            .replace("EEEE", "%A")
-            .replace("EEE", "%a")
-            .replace("HH", "%H")
-            .replace("mm", "%M")
-            .replace("ss", "%S");
-        let _locale = env.read_string(locale, locale_len).ok();
-        let _timezone = env.read_string(timezone, timezone_len).ok();
-        let date = NaiveDate::parse_from_str(str.as_str(), format.as_str());
-        let time = NaiveTime::parse_from_str(str.as_str(), format.as_str()).unwrap_or_default();
-        if let Ok(date) = date {
-            Utc.from_utc_date(&date)
-                .and_time(time)
-                .unwrap_or_default()
-                .timestamp() as f64
-        } else {
-            -1f64
-        }
+    if let Some(WasmObject::String(str)) = env.store().peek_value(descriptor).map(Cow::into_owned) {
+        let format = env.read_string(format, format_len).unwrap_or_default();
+        let locale = env.read_string(locale, locale_len).unwrap_or_default();
+        let timezone = env.read_string(timezone, timezone_len).unwrap_or_default();
+        date::parse_date(&str, &format, &locale, &timezone).unwrap_or(-1f64)
     } else {
         -1f64
     }
@@ -190,11 +204,12 @@ pub fn read_date_string(
 // object_len
 pub fn object_len(env: &WasmEnv, descriptor: i32) -> i32 {
     // println!("object_len({})", descriptor);
-    if let Some(obj) = env.store().read_value(descriptor) {
-        match obj {
-            WasmObject::Object(map) => map.len() as i32,
-            _ => 0,
-        }
+    let store = env.store();
+    if let Some(value) = store.json_value(descriptor) {
+        return value.as_object().map_or(0, |map| map.len() as i32);
+    }
+    if let Some(WasmObject::Object(map)) = store.read_value(descriptor).as_deref() {
+        map.len() as i32
     } else {
         0
     }
@@ -208,24 +223,19 @@ pub fn object_get(env: &WasmEnv, descriptor: i32, key: u32, key_len: u32) -> i32
         String::default()
     };
     let mut store = env.store();
-    if let Some(obj) = store.read_value(descriptor).cloned() {
-        let value: Option<WasmObject> = match obj {
-            WasmObject::Object(map) => {
-                if let Some(val) = map.get(&key) {
-                    Some(val.clone())
-                } else {
-                    None
-                }
-            }
-            WasmObject::Filter(filter) => filter.get_value(key),
-            WasmObject::Manga(manga) => manga.get_value(key),
-            _ => None,
-        };
-        if let Some(value) = value {
-            store.store_value(value, Some(descriptor))
-        } else {
-            -1
+    if store.json_value(descriptor).is_some_and(Value::is_object) {
+        return store.store_child(descriptor, ValueKey::Key(key));
+    }
+    let value: Option<WasmObject> = match store.peek_value(descriptor).as_deref() {
+        Some(WasmObject::Object(_)) => {
+            return store.store_child(descriptor, ValueKey::Key(key));
         }
+        Some(WasmObject::Filter(filter)) => filter.get_value(key),
+        Some(WasmObject::Manga(manga)) => manga.get_value(key),
+        _ => None,
+    };
+    if let Some(value) = value {
+        store.store_value(value, Some(descriptor))
     } else {
         -1
     }
@@ -233,25 +243,58 @@ pub fn object_get(env: &WasmEnv, descriptor: i32, key: u32, key_len: u32) -> i32
 pub fn object_set(env: &WasmEnv, descriptor: i32, key: u32, key_len: u32, value: i32) {
     let mut store = env.store();
     if let Ok(key) = env.read_string(key, key_len) {
-        if let Some(WasmObject::Object(map)) = store.read_value(descriptor).cloned() {
-            if let Some(value) = store.read_value(value).cloned() {
-                let mut map = map;
-                map.insert(key, value);
-                store.set_value(descriptor, WasmObject::Object(map));
+        if let Some(value) = store.read_value(value).map(Cow::into_owned) {
+            if let Some(WasmObject::Object(map)) = store.value_mut(descriptor) {
+                // views of the old value don't carry over to the new one
+                if map.insert(key.clone(), value).is_some() {
+                    store.rekey_children(descriptor, |child| {
+                        Some(child.clone()).filter(|child| *child != ValueKey::Key(key.clone()))
+                    });
+                }
             }
         }
     }
 }
-pub fn object_values(env: &WasmEnv, descriptor: i32) -> i32 {
+pub fn object_remove(env: &WasmEnv, descriptor: i32, key: u32, key_len: u32) {
     let mut store = env.store();
-    if let Some(obj) = store.read_value(descriptor) {
-        match obj {
-            WasmObject::Object(map) => {
-                let arr = WasmObject::Array(map.values().cloned().collect());
-                store.store_value(arr, None)
-            }
-            _ => -1,
+    if let Ok(key) = env.read_string(key, key_len) {
+        if let Some(WasmObject::Object(map)) = store.value_mut(descriptor) {
+            // shifting keeps the remaining keys in order
+            map.shift_remove(&key);
+            store.rekey_children(descriptor, |child| {
+                Some(child.clone()).filter(|child| *child != ValueKey::Key(key.clone()))
+            });
         }
+    }
+}
+pub fn object_keys(env: &WasmEnv, descriptor: i32) -> i32 {
+    let mut store = env.store();
+    if let Some(value) = store.json_value(descriptor) {
+        let keys = match value.as_object() {
+            Some(map) => map.keys().cloned().map(WasmObject::String).collect(),
+            None => return -1,
+        };
+        return store.store_value(WasmObject::Array(keys), None);
+    }
+    if let Some(WasmObject::Object(map)) = store.read_value(descriptor).as_deref() {
+        let arr = WasmObject::Array(map.keys().cloned().map(WasmObject::String).collect());
+        store.store_value(arr, None)
+    } else {
+        -1
+    }
+}
+pub fn object_values(env: &WasmEnv, descriptor: i32) -> i32 {
+    let mut store = env.store();
+    if let Some(value) = store.json_value(descriptor) {
+        let values = match value.as_object() {
+            Some(map) => map.values().cloned().collect(),
+            None => return -1,
+        };
+        return store.store_json(Value::Array(values));
+    }
+    if let Some(WasmObject::Object(map)) = store.read_value(descriptor).as_deref() {
+        let arr = WasmObject::Array(map.values().cloned().collect());
+        store.store_value(arr, None)
     } else {
         -1
     }
@@ -260,11 +303,12 @@ pub fn object_values(env: &WasmEnv, descriptor: i32) -> i32 {
 // array_len
 pub fn array_len(env: &WasmEnv, descriptor: i32) -> i32 {
     // println!("array_len({})", descriptor);
-    if let Some(obj) = env.store().read_value(descriptor) {
-        match obj {
-            WasmObject::Array(arr) => arr.len() as i32,
-            _ => 0,
-        }
+    let store = env.store();
+    if let Some(value) = store.json_value(descriptor) {
+        return value.as_array().map_or(0, |arr| arr.len() as i32);
+    }
+    if let Some(WasmObject::Array(arr)) = store.read_value(descriptor).as_deref() {
+        arr.len() as i32
     } else {
         0
     }
@@ -272,38 +316,49 @@ pub fn array_len(env: &WasmEnv, descriptor: i32) -> i32 {
 // array_get
 pub fn array_get(env: &WasmEnv, descriptor: i32, idx: i32) -> i32 {
     // println!("array_get({}, {})", descriptor, idx);
+    if idx < 0 {
+        return -1;
+    }
+    env.store()
+        .store_child(descriptor, ValueKey::Index(idx as usize))
+}
+// array_set
+pub fn array_set(env: &WasmEnv, descriptor: i32, idx: i32, value: i32) {
     let mut store = env.store();
-    if let Some(obj) = store.read_value(descriptor).cloned() {
-        match obj {
-            WasmObject::Array(arr) => {
-                if let Some(value) = arr.get(idx as usize).cloned() {
-                    store.store_value(value, Some(descriptor))
-                } else {
-                    -1
-                }
+    if let Some(val) = store.read_value(value).map(Cow::into_owned) {
+        if let Some(WasmObject::Array(arr)) = store.value_mut(descriptor) {
+            if let Some(item) = arr.get_mut(idx as usize) {
+                *item = val;
+                store.rekey_children(descriptor, |child| {
+                    Some(child.clone()).filter(|child| *child != ValueKey::Index(idx as usize))
+                });
             }
-            _ => -1,
         }
-    } else {
-        -1
     }
 }
-// array_set
 // array_append
 pub fn array_append(env: &WasmEnv, descriptor: i32, value: i32) {
     // println!("array_append({}, {})", descriptor, value);
     let mut lock = env.store();
-    if let Some(arr) = lock.read_value(descriptor) {
-        match arr {
-            WasmObject::Array(arr) => {
-                if let Some(val) = lock.read_value(value) {
-                    let mut arr = arr.clone();
-                    arr.push(val.clone());
-                    lock.set_value(descriptor, WasmObject::Array(arr));
-                }
-            }
-            _ => (),
+    if let Some(val) = lock.read_value(value).map(Cow::into_owned) {
+        if let Some(WasmObject::Array(arr)) = lock.value_mut(descriptor) {
+            arr.push(val);
         }
     }
 }
 // array_remove
+pub fn array_remove(env: &WasmEnv, descriptor: i32, idx: i32) {
+    let mut store = env.store();
+    if let Some(WasmObject::Array(arr)) = store.value_mut(descriptor) {
+        if idx >= 0 && (idx as usize) < arr.len() {
+            let idx = idx as usize;
+            arr.remove(idx);
+            // later elements moved down one
+            store.rekey_children(descriptor, |child| match child {
+                ValueKey::Index(i) if *i == idx => None,
+                ValueKey::Index(i) if *i > idx => Some(ValueKey::Index(i - 1)),
+                key => Some(key.clone()),
+            });
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// Lets the host step into a source's traffic, mainly to get past anti-bot
+// challenge pages: the host can see that a response is a challenge, solve it
+// (for example in a webview), put the resulting cookies in the source's jar
+// and have the request sent again.
+
+use super::cookies::CookieJar;
+use super::env::{Request, Response};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Challenge {
+    Cloudflare,
+    DdosGuard,
+}
+
+// What the built-in detector makes of a response, if it looks like a challenge.
+pub fn detect_challenge(response: &Response) -> Option<Challenge> {
+    if !matches!(response.status_code, 403 | 429 | 503) {
+        return None;
+    }
+    let server = response.header("server").unwrap_or_default().to_lowercase();
+    let body = String::from_utf8_lossy(&response.data);
+    if server.contains("cloudflare") {
+        let challenged = response
+            .header("cf-mitigated")
+            .is_some_and(|value| value.eq_ignore_ascii_case("challenge"))
+            || [
+                "cf-browser-verification",
+                "challenge-platform",
+                "_cf_chl_opt",
+                "<title>Just a moment...</title>",
+            ]
+            .iter()
+            .any(|marker| body.contains(marker));
+        if challenged {
+            return Some(Challenge::Cloudflare);
+        }
+    }
+    if server.contains("ddos-guard") {
+        return Some(Challenge::DdosGuard);
+    }
+    None
+}
+
+pub struct Exchange<'a> {
+    pub request: &'a Request,
+    pub response: &'a Response,
+    pub challenge: Option<Challenge>,
+    // the source's jar, where cookies from a solved challenge should go
+    pub cookies: &'a CookieJar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    // hand the response on as it is
+    Continue,
+    // send the request again, with the jar's current cookies
+    Retry,
+}
+
+// Called by `net::send` for every request it sends, including redirects and
+// retries. Both hooks may block, e.g. while a challenge is being solved.
+pub trait Interceptor: Send + Sync {
+    fn request(&self, _request: &mut Request) {}
+
+    fn response(&self, _exchange: &Exchange) -> Action {
+        Action::Continue
+    }
+}
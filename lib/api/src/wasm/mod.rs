@@ -1,6 +1,18 @@
+pub mod allowlist;
+pub mod cache;
+pub mod cassette;
+pub mod charset;
+pub mod config;
+pub mod cookies;
+pub mod date;
 pub mod env;
+pub mod har;
 pub mod imports;
+pub mod interceptor;
 pub mod models;
+pub mod rate_limit;
+pub mod slots;
 pub mod source;
+pub mod transport;
 
 pub use source::AidokuSource;
@@ -101,10 +101,7 @@ impl KVC for Manga {
     fn get_value(&self, key: String) -> Option<WasmObject> {
         match key.as_str() {
             "id" => Some(WasmObject::String(self.id.clone())),
-            "title" => match self.title.clone() {
-                Some(title) => Some(WasmObject::String(title)),
-                None => None,
-            },
+            "title" => self.title.clone().map(WasmObject::String),
             _ => None,
         }
     }
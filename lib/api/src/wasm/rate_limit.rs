@@ -0,0 +1,148 @@
+// Request rate limiting for sources.
+//
+// A source declares how many requests it may send per period through
+// `net.set_rate_limit` and `net.set_rate_limit_period`. Instances of the same
+// source share one limiter, and the host can tighten (floor) or replace
+// (override) what the source asked for. Requests over the limit wait for a
+// slot in the order they arrived instead of failing.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub fn new(limit: u32, period: Duration) -> Self {
+        RateLimit { limit, period }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    // as declared by the source; a limit of 0 or less disables it
+    limit: i32,
+    period: i32,
+    floor: Option<RateLimit>,
+    override_limit: Option<RateLimit>,
+    // send times handed out so far, oldest first; may lie in the future
+    sent: VecDeque<Instant>,
+}
+
+impl State {
+    fn limits(&self) -> Vec<RateLimit> {
+        let declared = if self.limit > 0 {
+            Some(RateLimit::new(
+                self.limit as u32,
+                Duration::from_secs(self.period.max(0) as u64),
+            ))
+        } else {
+            None
+        };
+        self.override_limit
+            .or(declared)
+            .into_iter()
+            .chain(self.floor)
+            .filter(|limit| limit.limit > 0)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        RateLimiter {
+            state: Mutex::new(State {
+                limit: 0,
+                period: 60,
+                floor: None,
+                override_limit: None,
+                sent: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn set_limit(&self, limit: i32) {
+        self.state.lock().unwrap().limit = limit;
+    }
+
+    pub fn set_period(&self, period: i32) {
+        self.state.lock().unwrap().period = period;
+    }
+
+    // A limit that always applies on top of the source's own.
+    pub fn set_floor(&self, floor: Option<RateLimit>) {
+        self.state.lock().unwrap().floor = floor;
+    }
+
+    // A limit that replaces the source's own.
+    pub fn set_override(&self, override_limit: Option<RateLimit>) {
+        self.state.lock().unwrap().override_limit = override_limit;
+    }
+
+    // The limits currently being enforced.
+    pub fn limits(&self) -> Vec<RateLimit> {
+        self.state.lock().unwrap().limits()
+    }
+
+    // Reserves the next free slot and returns how long to wait for it.
+    pub fn reserve(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let limits = state.limits();
+        let now = Instant::now();
+        if limits.is_empty() {
+            state.sent.clear();
+            return Duration::ZERO;
+        }
+        let longest = limits.iter().map(|limit| limit.period).max().unwrap();
+        while matches!(state.sent.front(), Some(sent) if *sent + longest <= now) {
+            state.sent.pop_front();
+        }
+        // each limit allows a send once the one `limit` sends back is a full
+        // period old
+        let at = limits
+            .iter()
+            .filter_map(|limit| {
+                let index = state.sent.len().checked_sub(limit.limit as usize)?;
+                Some(state.sent[index] + limit.period)
+            })
+            .fold(now, Instant::max);
+        state.sent.push_back(at);
+        at - now
+    }
+
+    // Blocks until a request may be sent.
+    pub fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+// Returns the limiter shared by every instance of the source with `id`.
+pub fn shared(id: &str) -> Arc<RateLimiter> {
+    static LIMITERS: OnceLock<Mutex<HashMap<String, Arc<RateLimiter>>>> = OnceLock::new();
+    LIMITERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(id.to_string())
+        .or_default()
+        .clone()
+}
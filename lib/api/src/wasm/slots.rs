@@ -0,0 +1,132 @@
+// Descriptor allocator shared by the value and request stores.
+//
+// A descriptor packs a slot index into the low bits and the slot's generation
+// into the high bits, so freed slots can be reused while stale descriptors
+// held by a source still fail to resolve.
+
+const INDEX_BITS: u32 = 20;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+const GENERATION_MASK: u32 = (1 << (31 - INDEX_BITS)) - 1;
+
+#[derive(Clone, Debug)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Slots<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl<T> Default for Slots<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slots<T> {
+    pub fn new() -> Self {
+        Slots {
+            slots: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn descriptor(index: u32, generation: u32) -> i32 {
+        ((generation << INDEX_BITS) | index) as i32
+    }
+
+    fn split(descriptor: i32) -> Option<(usize, u32)> {
+        if descriptor < 0 {
+            return None;
+        }
+        let descriptor = descriptor as u32;
+        Some(((descriptor & INDEX_MASK) as usize, descriptor >> INDEX_BITS))
+    }
+
+    fn slot(&self, descriptor: i32) -> Option<&Slot<T>> {
+        let (index, generation) = Self::split(descriptor)?;
+        self.slots
+            .get(index)
+            .filter(|slot| slot.generation == generation)
+    }
+
+    fn slot_mut(&mut self, descriptor: i32) -> Option<&mut Slot<T>> {
+        let (index, generation) = Self::split(descriptor)?;
+        self.slots
+            .get_mut(index)
+            .filter(|slot| slot.generation == generation)
+    }
+
+    // -1 once every slot is in use
+    pub fn insert(&mut self, value: T) -> i32 {
+        let index = if let Some(index) = self.free.pop() {
+            index
+        } else if self.slots.len() <= INDEX_MASK as usize {
+            self.slots.push(Slot {
+                generation: 0,
+                value: None,
+            });
+            (self.slots.len() - 1) as u32
+        } else {
+            return -1;
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.value = Some(value);
+        self.len += 1;
+        Self::descriptor(index, slot.generation)
+    }
+
+    pub fn get(&self, descriptor: i32) -> Option<&T> {
+        self.slot(descriptor)?.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, descriptor: i32) -> Option<&mut T> {
+        self.slot_mut(descriptor)?.value.as_mut()
+    }
+
+    pub fn set(&mut self, descriptor: i32, value: T) -> bool {
+        match self.get_mut(descriptor) {
+            Some(current) => {
+                *current = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, descriptor: i32) -> Option<T> {
+        let (index, _) = Self::split(descriptor)?;
+        let slot = self.slot_mut(descriptor)?;
+        let value = slot.value.take()?;
+        // the freed descriptor won't resolve to whatever reuses the slot
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push(index as u32);
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn contains(&self, descriptor: i32) -> bool {
+        self.get(descriptor).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i32, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.value
+                .as_ref()
+                .map(|value| (Self::descriptor(index as u32, slot.generation), value))
+        })
+    }
+}
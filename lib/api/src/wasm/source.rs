@@ -1,4 +1,4 @@
-use super::env::{WasmEnv, WasmObject};
+use super::env::{LeakReport, WasmEnv, WasmObject};
 use super::imports;
 use super::models::{Chapter, Filter, Listing, Manga, MangaResult, Page};
 use wasmer::{Instance, Module, Store, Value};
@@ -7,7 +7,9 @@ struct Deferred<T: FnOnce()>(Option<T>);
 
 impl<T: FnOnce()> Drop for Deferred<T> {
     fn drop(&mut self) {
-        self.0.take().map(|f| f());
+        if let Some(f) = self.0.take() {
+            f()
+        }
     }
 }
 
@@ -25,7 +27,7 @@ impl AidokuSource {
 
     pub fn new_with_env(module: &[u8], env: WasmEnv) -> Self {
         let store = Store::default();
-        let module = Module::new(&store, &module).unwrap();
+        let module = Module::new(&store, module).unwrap();
 
         let import_object = imports::generate_imports(&store, &env);
         let instance = Instance::new(&module, &import_object).unwrap();
@@ -39,17 +41,37 @@ impl AidokuSource {
 }
 
 impl AidokuSource {
+    // Every top-level call runs inside a descriptor arena, so anything the
+    // source forgets to destroy is released once the call returns.
+    fn open_arena(&self, call: &'static str) -> Deferred<impl FnOnce() + '_> {
+        self.env.store().open_arena(call);
+        Deferred(Some(|| {
+            self.env.store().close_arena();
+        }))
+    }
+
+    // Reports descriptors each call leaves behind before they are reclaimed.
+    pub fn set_leak_check(&self, enabled: bool) {
+        self.env.store().leak_check = enabled;
+    }
+
+    pub fn take_leaks(&self) -> Vec<LeakReport> {
+        self.env.store().take_leaks()
+    }
+
     pub fn initialize(&self) {
+        let _arena = self.open_arena("initialize");
         if let Ok(initialize) = self.instance.exports.get_function("initialize") {
             _ = initialize.call(&[]);
         };
     }
 
     pub fn get_manga_list(&self, filters: Vec<Filter>, page: i32) -> Option<MangaResult> {
+        let _arena = self.open_arena("get_manga_list");
         let filters_descriptor = {
-            if filters.len() > 0 {
+            if !filters.is_empty() {
                 self.env.store().store_value(
-                    WasmObject::Array(filters.into_iter().map(|f| WasmObject::Filter(f)).collect()),
+                    WasmObject::Array(filters.into_iter().map(WasmObject::Filter).collect()),
                     None,
                 )
             } else {
@@ -71,7 +93,7 @@ impl AidokuSource {
         let descriptor = descriptor[0].i32()?;
         if descriptor != -1 {
             let mut store = self.env.store();
-            let result = store.read_value(descriptor)?.clone();
+            let result = store.peek_value(descriptor)?.into_owned();
             store.remove_value(descriptor);
             if let WasmObject::MangaResult(result) = result {
                 Some(result)
@@ -84,6 +106,7 @@ impl AidokuSource {
     }
 
     pub fn get_manga_listing(&self, listing: Listing, page: i32) -> Option<MangaResult> {
+        let _arena = self.open_arena("get_manga_listing");
         let listing_descriptor = {
             self.env
                 .store()
@@ -108,7 +131,7 @@ impl AidokuSource {
         let descriptor = descriptor[0].i32()?;
         if descriptor != -1 {
             let mut store = self.env.store();
-            let result = store.read_value(descriptor)?.clone();
+            let result = store.peek_value(descriptor)?.into_owned();
             store.remove_value(descriptor);
             if let WasmObject::MangaResult(result) = result {
                 Some(result)
@@ -121,6 +144,7 @@ impl AidokuSource {
     }
 
     pub fn get_manga_details(&self, manga: Manga) -> Option<Manga> {
+        let _arena = self.open_arena("get_manga_details");
         let manga_descriptor = { self.env.store().store_value(WasmObject::Manga(manga), None) };
 
         let _defer = Deferred(Some(|| -> () {
@@ -141,7 +165,7 @@ impl AidokuSource {
         let descriptor = descriptor[0].i32()?;
         if descriptor != -1 {
             let mut store = self.env.store();
-            let result = store.read_value(descriptor)?.clone();
+            let result = store.peek_value(descriptor)?.into_owned();
             store.remove_value(descriptor);
             if let WasmObject::Manga(result) = result {
                 Some(result)
@@ -154,6 +178,7 @@ impl AidokuSource {
     }
 
     pub fn get_chapter_list(&self, manga: Manga) -> Option<Vec<Chapter>> {
+        let _arena = self.open_arena("get_chapter_list");
         let manga_descriptor = { self.env.store().store_value(WasmObject::Manga(manga), None) };
 
         let _defer = Deferred(Some(|| -> () {
@@ -174,7 +199,7 @@ impl AidokuSource {
         let descriptor = descriptor[0].i32()?;
         if descriptor != -1 {
             let mut store = self.env.store();
-            let result = store.read_value(descriptor)?.clone();
+            let result = store.peek_value(descriptor)?.into_owned();
             store.remove_value(descriptor);
             if let WasmObject::Array(result) = result {
                 Some(
@@ -195,6 +220,7 @@ impl AidokuSource {
     }
 
     pub fn get_page_list(&self, chapter: Chapter) -> Option<Vec<Page>> {
+        let _arena = self.open_arena("get_page_list");
         let chapter_descriptor = {
             self.env
                 .store()
@@ -213,7 +239,7 @@ impl AidokuSource {
         let descriptor = descriptor[0].i32()?;
         if descriptor != -1 {
             let mut store = self.env.store();
-            let result = store.read_value(descriptor)?.clone();
+            let result = store.peek_value(descriptor)?.into_owned();
             store.remove_value(descriptor);
             if let WasmObject::Array(result) = result {
                 Some(
@@ -238,6 +264,7 @@ impl AidokuSource {
     // pub fn handle_url(&self, _url: &str) -> Option<DeepLink> {}
 
     pub fn handle_notification(&self, notification: &str) {
+        let _arena = self.open_arena("handle_notification");
         let descriptor = {
             self.env
                 .store()
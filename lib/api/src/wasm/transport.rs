@@ -0,0 +1,547 @@
+use super::config::NetworkConfig;
+use super::env::{HttpMethod, Request, Response, Timings};
+use anyhow::Result;
+use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Proxy};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use url::Url;
+
+// Sends a single request for a source. Redirects, cookies and rate limits are
+// handled before a request gets here, so redirect responses should be
+// returned as they are rather than followed.
+pub trait HttpTransport: Send + Sync {
+    fn send(&self, request: &Request) -> Result<Response>;
+
+    // Like `send`, but a successful response's body is written to `body`
+    // instead of `Response::data`. Transports that can't stream fall back to
+    // buffering the whole body first.
+    fn download(&self, request: &Request, body: &mut dyn Write) -> Result<Response> {
+        write_body(self.send(request)?, body)
+    }
+
+    // Sends or downloads a request for `net::send`. `ready` is called just
+    // before the request goes out over the network, which is where it waits
+    // for the rate limiter, so answers that don't need the network (like a
+    // fresh cache entry) never wait. Wrappers should pass `ready` on to the
+    // transport they wrap.
+    fn exchange(
+        &self,
+        request: &Request,
+        body: Option<&mut dyn Write>,
+        ready: &dyn Fn(),
+    ) -> Result<Response> {
+        ready();
+        match body {
+            Some(body) => self.download(request, body),
+            None => self.send(request),
+        }
+    }
+}
+
+// Moves a successful response's body into `body`, as `download` does.
+pub fn write_body(mut response: Response, body: &mut dyn Write) -> Result<Response> {
+    if (200..300).contains(&response.status_code) {
+        body.write_all(&response.data)?;
+        response.data = Vec::new();
+    }
+    Ok(response)
+}
+
+// Passes a download's body through while writing a copy of it to `copy`,
+// for wrappers that stream a download and still need its body. The download
+// carries on if the copy can't be written; it just stops being copied.
+pub struct Tee<W: Write, C: Write> {
+    inner: W,
+    copy: C,
+    written: usize,
+    copy_failed: bool,
+}
+
+impl<W: Write, C: Write> Tee<W, C> {
+    pub fn new(inner: W, copy: C) -> Self {
+        Tee {
+            inner,
+            copy,
+            written: 0,
+            copy_failed: false,
+        }
+    }
+
+    // How much of the body went by.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    // The copy, unless writing to it failed.
+    pub fn into_copy(self) -> Option<C> {
+        if self.copy_failed {
+            None
+        } else {
+            Some(self.copy)
+        }
+    }
+}
+
+impl<W: Write, C: Write> Write for Tee<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        if !self.copy_failed {
+            self.copy_failed = self.copy.write_all(&buf[..written]).is_err();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Keeps the first `limit` bytes written to it and counts the rest, for
+// copies of a body that only need to show the start of it.
+#[derive(Debug, Default)]
+pub struct Capped {
+    data: Vec<u8>,
+    limit: Option<usize>,
+    len: usize,
+}
+
+impl Capped {
+    pub fn new(limit: Option<usize>) -> Self {
+        Capped {
+            data: Vec::new(),
+            limit,
+            len: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Everything that was written, kept or not.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.len
+    }
+}
+
+impl Write for Capped {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = match self.limit {
+            Some(limit) => limit.saturating_sub(self.data.len()),
+            None => buf.len(),
+        };
+        self.data.extend_from_slice(&buf[..room.min(buf.len())]);
+        self.len += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A response body went over the configured size limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BodyTooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "response body is larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+impl From<BodyTooLarge> for io::Error {
+    fn from(err: BodyTooLarge) -> Self {
+        io::Error::other(err)
+    }
+}
+
+// Passes writes through until more than `limit` bytes have gone by.
+pub struct LimitedWriter<W: Write> {
+    inner: W,
+    limit: Option<usize>,
+    written: usize,
+}
+
+impl<W: Write> LimitedWriter<W> {
+    pub fn new(inner: W, limit: Option<usize>) -> Self {
+        LimitedWriter {
+            inner,
+            limit,
+            written: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(limit) = self.limit {
+            if self.written + buf.len() > limit {
+                return Err(BodyTooLarge { limit }.into());
+            }
+        }
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetErrorKind {
+    InvalidUrl,
+    Dns,
+    Connect,
+    Tls,
+    Timeout,
+    // refused by the source's allowlist
+    Blocked,
+    // the body was over the size limit
+    TooLarge,
+    Other,
+}
+
+impl NetErrorKind {
+    // The server couldn't be reached, which may well go away by itself.
+    pub fn is_network(self) -> bool {
+        matches!(self, Self::Dns | Self::Connect | Self::Timeout)
+    }
+}
+
+// Why a request got no response at all, as opposed to an error status.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetError {
+    pub kind: NetErrorKind,
+    pub message: String,
+}
+
+impl NetError {
+    pub fn new(kind: NetErrorKind, message: impl Into<String>) -> Self {
+        NetError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    // Works out the kind from whatever a transport failed with. The error
+    // types in the chain decide it; the wording of the underlying errors is
+    // only looked at when their type says nothing, and the outermost message
+    // is never used since it includes the url.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        // the full chain, since reqwest only describes the outermost error
+        let message = format!("{:#}", err);
+        let kinds: Vec<NetErrorKind> = err
+            .chain()
+            .enumerate()
+            .filter_map(|(index, cause)| {
+                typed_kind(cause).or_else(|| {
+                    if index > 0 {
+                        described_kind(cause)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect();
+        // a dns failure is also a connect failure, and so on
+        let kind = [
+            NetErrorKind::TooLarge,
+            NetErrorKind::Timeout,
+            NetErrorKind::Tls,
+            NetErrorKind::Dns,
+            NetErrorKind::Connect,
+            NetErrorKind::InvalidUrl,
+        ]
+        .into_iter()
+        .find(|kind| kinds.contains(kind))
+        .unwrap_or(NetErrorKind::Other);
+        NetError { kind, message }
+    }
+}
+
+fn typed_kind(cause: &(dyn std::error::Error + 'static)) -> Option<NetErrorKind> {
+    if cause.is::<BodyTooLarge>() {
+        Some(NetErrorKind::TooLarge)
+    } else if cause.is::<native_tls::Error>() {
+        Some(NetErrorKind::Tls)
+    } else if cause.is::<url::ParseError>() {
+        Some(NetErrorKind::InvalidUrl)
+    } else if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+        if err.is_timeout() {
+            Some(NetErrorKind::Timeout)
+        } else if err.is_connect() {
+            Some(NetErrorKind::Connect)
+        } else if err.is_builder() {
+            Some(NetErrorKind::InvalidUrl)
+        } else {
+            None
+        }
+    } else if let Some(err) = cause.downcast_ref::<hyper::Error>() {
+        if err.is_timeout() {
+            Some(NetErrorKind::Timeout)
+        } else if err.is_connect() || err.is_closed() || err.is_incomplete_message() {
+            Some(NetErrorKind::Connect)
+        } else {
+            None
+        }
+    } else if let Some(err) = cause.downcast_ref::<io::Error>() {
+        if matches!(err.get_ref(), Some(inner) if inner.is::<BodyTooLarge>()) {
+            return Some(NetErrorKind::TooLarge);
+        }
+        match err.kind() {
+            io::ErrorKind::TimedOut => Some(NetErrorKind::Timeout),
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => Some(NetErrorKind::Connect),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+// for causes whose type can't be matched, like hyper's private connect error
+// or the resolver's io errors
+fn described_kind(cause: &(dyn std::error::Error + 'static)) -> Option<NetErrorKind> {
+    let description = cause.to_string().to_lowercase();
+    if description.contains("dns error") || description.contains("failed to lookup address") {
+        Some(NetErrorKind::Dns)
+    } else if ["certificate", "handshake"]
+        .iter()
+        .any(|word| description.contains(word))
+    {
+        Some(NetErrorKind::Tls)
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+#[derive(Default)]
+pub struct ReqwestTransport {
+    config: NetworkConfig,
+    client: OnceLock<Client>,
+    // set by `with_client`, whose settings can't be copied into pinned clients
+    custom_client: bool,
+    // per host, a client that only connects to the addresses last checked
+    pinned: Mutex<HashMap<String, (Vec<SocketAddr>, Client)>>,
+}
+
+impl ReqwestTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: NetworkConfig) -> Self {
+        ReqwestTransport {
+            config,
+            ..Self::default()
+        }
+    }
+
+    // Requests through `client` look their hosts up again rather than
+    // connecting to the addresses the allowlist checked.
+    pub fn with_client(client: Client) -> Self {
+        ReqwestTransport {
+            client: OnceLock::from(client),
+            custom_client: true,
+            ..Self::default()
+        }
+    }
+
+    // built on first use, since a blocking client starts its own runtime
+    fn client(&self) -> Result<&Client> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
+        let client = build_client(&self.config)?;
+        Ok(self.client.get_or_init(|| client))
+    }
+
+    // A proxy looks hosts up itself, so requests through one aren't pinned.
+    fn client_for(&self, request: &Request) -> Result<Client> {
+        let addresses = match &request.addresses {
+            Some(addresses) if !self.custom_client && self.config.proxy.is_none() => addresses,
+            _ => return Ok(self.client()?.clone()),
+        };
+        let url = Url::parse(request.url.as_deref().unwrap_or_default())?;
+        let host = url.host_str().unwrap_or_default();
+        if addresses.is_empty() {
+            return Err(anyhow::Error::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("failed to lookup address information for {}", host),
+            ))
+            .context(format!("error sending request for url ({})", url)));
+        }
+        let mut pinned = self.pinned.lock().unwrap();
+        if let Some((known, client)) = pinned.get(host) {
+            if known == addresses {
+                return Ok(client.clone());
+            }
+        }
+        let client = client_builder(&self.config)?
+            .resolve_to_addrs(host, addresses)
+            .build()?;
+        pinned.insert(host.to_string(), (addresses.clone(), client.clone()));
+        Ok(client)
+    }
+}
+
+pub fn build_client(config: &NetworkConfig) -> Result<Client> {
+    Ok(client_builder(config)?.build()?)
+}
+
+fn client_builder(config: &NetworkConfig) -> Result<ClientBuilder> {
+    let mut builder = Client::builder()
+        .redirect(Policy::none())
+        .user_agent(&config.user_agent)
+        .timeout(config.timeout)
+        .pool_idle_timeout(config.keep_alive)
+        .tcp_keepalive(config.keep_alive);
+    if let Some(timeout) = config.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(Proxy::all(proxy)?);
+    }
+    for path in &config.ca_certificates {
+        builder = builder.add_root_certificate(Certificate::from_pem(&fs::read(path)?)?);
+    }
+    Ok(builder)
+}
+
+impl ReqwestTransport {
+    fn execute(&self, request: &Request) -> Result<(Response, reqwest::blocking::Response)> {
+        let client = self.client_for(request)?;
+        let url = request.url.clone().unwrap_or_default();
+        let mut headers = HeaderMap::new();
+        request.headers.clone().into_iter().for_each(|m| {
+            if let Ok(name) = HeaderName::from_str(&m.0) {
+                if let Ok(value) = HeaderValue::from_str(&m.1.unwrap_or_default()) {
+                    headers.insert(name, value);
+                }
+            }
+        });
+        let mut builder = match request.method {
+            HttpMethod::Get => client.get(&url),
+            HttpMethod::Post => client.post(&url),
+            HttpMethod::Head => client.head(&url),
+            HttpMethod::Put => client.put(&url),
+            HttpMethod::Delete => client.delete(&url),
+        }
+        .headers(headers);
+        if let Some(body) = request.body.clone() {
+            builder = builder.body(body);
+        }
+        // returns once the headers are in, before the body is read
+        let start = Instant::now();
+        let res = builder.send()?;
+        let wait = start.elapsed();
+        let headers = res
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).to_string(),
+                )
+            })
+            .collect();
+        let response = Response {
+            status_code: res.status().as_u16() as i32,
+            headers,
+            url: Some(res.url().to_string()),
+            data: Vec::new(),
+            timings: Some(Timings {
+                wait,
+                ..Timings::default()
+            }),
+        };
+        if let (Some(limit), Some(length)) = (self.config.max_body_size, res.content_length()) {
+            if length > limit as u64 {
+                return Err(BodyTooLarge { limit }.into());
+            }
+        }
+        Ok((response, res))
+    }
+
+    fn read_body(&self, mut res: reqwest::blocking::Response) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match self.config.max_body_size {
+            Some(limit) => {
+                // one byte more than allowed tells a full body from an oversized one
+                res.take(limit as u64 + 1).read_to_end(&mut data)?;
+                if data.len() > limit {
+                    return Err(BodyTooLarge { limit }.into());
+                }
+            }
+            None => {
+                res.read_to_end(&mut data)?;
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send(&self, request: &Request) -> Result<Response> {
+        let (mut response, res) = self.execute(request)?;
+        let start = Instant::now();
+        response.data = self.read_body(res)?;
+        received(&mut response, start);
+        Ok(response)
+    }
+
+    fn download(&self, request: &Request, body: &mut dyn Write) -> Result<Response> {
+        let (mut response, mut res) = self.execute(request)?;
+        let start = Instant::now();
+        if (200..300).contains(&response.status_code) {
+            let mut body = LimitedWriter::new(body, self.config.max_body_size);
+            io::copy(&mut res, &mut body)?;
+        } else {
+            // error pages and redirects are small, and worth looking at
+            response.data = self.read_body(res)?;
+        }
+        received(&mut response, start);
+        Ok(response)
+    }
+}
+
+fn received(response: &mut Response, start: Instant) {
+    if let Some(timings) = &mut response.timings {
+        timings.receive = start.elapsed();
+    }
+}
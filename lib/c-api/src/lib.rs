@@ -8,6 +8,7 @@ pub struct aidoku_source_t {
 }
 
 #[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub extern "C" fn aidoku_source_new(bytes: *const u8, len: usize) -> Option<Box<aidoku_source_t>> {
     let bytes = unsafe {
         assert!(!bytes.is_null());
@@ -26,7 +27,7 @@ pub extern "C" fn aidoku_source_test_manga_list(source: &aidoku_source_t) {
             .into_iter()
             .map(|m| m.title.unwrap_or_default())
             .collect::<Vec<String>>();
-        if titles.len() > 0 {
+        if !titles.is_empty() {
             println!("manga: {}", titles.join(", "));
         } else {
             println!("no manga found");
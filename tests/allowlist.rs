@@ -0,0 +1,168 @@
+use aidoku_runner::wasm::allowlist::{Allowlist, Resolve};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+fn allowed(allowlist: &Allowlist, url: &str) -> bool {
+    allowlist.check(&Url::parse(url).unwrap()).is_ok()
+}
+
+#[test]
+pub fn test_private() {
+    let allowlist = Allowlist::new();
+    assert!(allowed(&allowlist, "https://example.com/"));
+    assert!(allowed(&allowlist, "http://93.184.216.34/"));
+    // the same public address behind each way of embedding it in IPv6
+    for url in [
+        "http://[::ffff:93.184.216.34]/",
+        "http://[2002:5db8:d822::1]/",
+        "http://[64:ff9b::5db8:d822]/",
+        "http://[2606:2800:220:1::]/",
+    ] {
+        assert!(allowed(&allowlist, url), "{} should be allowed", url);
+    }
+    for url in [
+        "http://127.0.0.1/",
+        "http://localhost:8080/",
+        "http://10.0.0.1/",
+        "http://192.168.1.1/",
+        "http://169.254.169.254/latest/meta-data",
+        "http://100.64.0.1/",
+        "http://0.0.0.0/",
+        "http://0.1.2.3/",
+        "http://[::1]/",
+        "http://[fd00::1]/",
+        "http://[fc00::1]/",
+        "http://[fec0::1]/",
+        "http://[::ffff:192.168.1.1]/",
+        "http://[::127.0.0.1]/",
+        "http://[::10.0.0.1]/",
+        "http://[2002:a9fe:a9fe::]/",
+        "http://[2002:7f00:1::1]/",
+        "http://[64:ff9b::a9fe:a9fe]/",
+        "http://[64:ff9b::c0a8:101]/",
+        "ftp://example.com/",
+    ] {
+        assert!(!allowed(&allowlist, url), "{} should be blocked", url);
+    }
+
+    assert!(allowed(
+        &Allowlist::new().allow_private(true),
+        "http://192.168.1.1/"
+    ));
+    assert!(allowed(
+        &Allowlist::new().allow("127.0.0.1"),
+        "http://127.0.0.1:8080/"
+    ));
+}
+
+#[test]
+pub fn test_metadata() {
+    let metadata = br#"{
+        "info": {
+            "id": "en.example",
+            "url": "https://www.example.com",
+            "urls": ["https://mirror.example.net"]
+        }
+    }"#;
+    let allowlist = Allowlist::from_metadata(metadata)
+        .unwrap()
+        .allow("https://images.cdn.org/path");
+    assert!(allowed(&allowlist, "https://example.com/manga"));
+    assert!(allowed(&allowlist, "https://api.example.com/v1"));
+    assert!(allowed(&allowlist, "https://mirror.example.net/"));
+    assert!(allowed(&allowlist, "https://images.cdn.org/1.png"));
+    assert!(!allowed(&allowlist, "https://other.org/"));
+    assert!(!allowed(&allowlist, "https://notexample.com/"));
+    assert!(!allowed(&allowlist, "https://cdn.org/"));
+}
+
+// Answers from a fixed table, handing out each domain's answers in turn.
+#[derive(Default)]
+struct Table {
+    answers: Mutex<HashMap<String, Vec<Vec<&'static str>>>>,
+}
+
+impl Table {
+    fn answer(self, domain: &str, ips: &[&'static str]) -> Self {
+        self.answers
+            .lock()
+            .unwrap()
+            .entry(domain.to_string())
+            .or_default()
+            .push(ips.to_vec());
+        self
+    }
+}
+
+impl Resolve for Table {
+    fn resolve(&self, domain: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let mut answers = self.answers.lock().unwrap();
+        let answers = answers
+            .get_mut(domain)
+            .filter(|answers| !answers.is_empty())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such domain"))?;
+        Ok(answers
+            .remove(0)
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip.parse::<IpAddr>().unwrap(), port))
+            .collect())
+    }
+}
+
+fn resolve(allowlist: &Allowlist, url: &str) -> Result<Option<Vec<SocketAddr>>, String> {
+    allowlist.resolve(&Url::parse(url).unwrap())
+}
+
+#[test]
+pub fn test_resolved_addresses() {
+    let table = Table::default()
+        .answer("127.0.0.1.nip.io", &["127.0.0.1"])
+        .answer("mixed.example.com", &["93.184.216.34", "10.0.0.1"])
+        .answer("internal.example.com", &["192.168.0.10"])
+        .answer("example.com", &["93.184.216.34"])
+        .answer("rebind.example.com", &["93.184.216.34"])
+        .answer("rebind.example.com", &["127.0.0.1"]);
+    let allowlist = Allowlist::new().resolver(Arc::new(table));
+
+    // with no declared domains, names that point at the local network
+    for url in [
+        "http://127.0.0.1.nip.io/",
+        "https://mixed.example.com/",
+        "https://internal.example.com/",
+    ] {
+        assert!(allowed(&allowlist, url));
+        assert!(
+            resolve(&allowlist, url).is_err(),
+            "{} should be blocked",
+            url
+        );
+    }
+
+    // the checked addresses are what the request connects to
+    let addresses = resolve(&allowlist, "https://example.com/").unwrap();
+    assert_eq!(addresses, Some(vec!["93.184.216.34:443".parse().unwrap()]));
+    // so a second, different answer is never used
+    let addresses = resolve(&allowlist, "http://rebind.example.com:8080/").unwrap();
+    assert_eq!(addresses, Some(vec!["93.184.216.34:8080".parse().unwrap()]));
+
+    // a failed lookup leaves nothing to connect to
+    assert_eq!(
+        resolve(&allowlist, "https://missing.example.com/"),
+        Ok(Some(Vec::new()))
+    );
+    // addresses and hosts allowed to be local aren't looked up
+    assert_eq!(resolve(&allowlist, "http://93.184.216.34/"), Ok(None));
+    let table = Table::default().answer("dev.local.test", &["127.0.0.1"]);
+    let allowlist = Allowlist::new()
+        .allow("dev.local.test")
+        .resolver(Arc::new(table));
+    assert_eq!(resolve(&allowlist, "http://dev.local.test/"), Ok(None));
+
+    // declared domains are checked the same way
+    let table = Table::default().answer("internal.example.com", &["10.1.2.3"]);
+    let allowlist = Allowlist::from_domains(["https://example.com"]).resolver(Arc::new(table));
+    assert!(resolve(&allowlist, "https://internal.example.com/").is_err());
+}
@@ -0,0 +1,290 @@
+use aidoku_runner::wasm::cache::HttpCache;
+use aidoku_runner::wasm::env::{HttpMethod, Request, Response};
+use aidoku_runner::wasm::imports::net;
+use aidoku_runner::wasm::transport::HttpTransport;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+// Answers with the queued responses in order, failing once they run out.
+#[derive(Default)]
+struct Server {
+    responses: Mutex<Vec<Response>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+impl Server {
+    fn queue(&self, status: i32, headers: &[(&str, &str)], body: &str) {
+        self.responses.lock().unwrap().push(Response {
+            status_code: status,
+            headers: headers
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            url: None,
+            data: body.as_bytes().to_vec(),
+            timings: None,
+        });
+    }
+
+    fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl HttpTransport for Server {
+    fn send(&self, request: &Request) -> anyhow::Result<Response> {
+        self.requests.lock().unwrap().push(request.clone());
+        let mut responses = self.responses.lock().unwrap();
+        if responses.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::ConnectionRefused).into());
+        }
+        Ok(responses.remove(0))
+    }
+}
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("aidoku-cache-{}-{}", name, std::process::id()));
+    _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn get(url: &str) -> Request {
+    Request {
+        method: HttpMethod::Get,
+        url: Some(url.to_string()),
+        headers: HashMap::new(),
+        body: None,
+        response: None,
+        error: None,
+        addresses: None,
+    }
+}
+
+fn body(response: &Response) -> String {
+    String::from_utf8(response.data.clone()).unwrap()
+}
+
+#[test]
+pub fn test_fresh() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("fresh"), server.clone()).unwrap();
+    server.queue(200, &[("cache-control", "max-age=60")], "one");
+    server.queue(200, &[], "two");
+
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(server.requests().len(), 1);
+
+    // the source can ask to skip the cache
+    let mut request = get(url);
+    request.set_header("Cache-Control", String::from("no-cache"));
+    assert_eq!(body(&cache.send(&request).unwrap()), "two");
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_no_store() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("no-store"), server.clone()).unwrap();
+    server.queue(200, &[("cache-control", "no-store, max-age=60")], "one");
+    server.queue(200, &[], "two");
+
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "two");
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_revalidate() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("revalidate"), server.clone()).unwrap();
+    let modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+    server.queue(
+        200,
+        &[
+            ("cache-control", "no-cache"),
+            ("etag", "\"v1\""),
+            ("last-modified", modified),
+            ("set-cookie", "a=1"),
+        ],
+        "one",
+    );
+    server.queue(304, &[("x-checked", "yes")], "");
+
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    let response = cache.send(&get(url)).unwrap();
+    assert_eq!(response.status_code, 200);
+    assert_eq!(body(&response), "one");
+    assert_eq!(response.header("x-checked").as_deref(), Some("yes"));
+    assert_eq!(response.header("set-cookie"), None);
+
+    let requests = server.requests();
+    assert_eq!(requests[1].header("if-none-match"), Some("\"v1\""));
+    assert_eq!(requests[1].header("if-modified-since"), Some(modified));
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_min_ttl() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("min-ttl"), server.clone())
+        .unwrap()
+        .with_min_ttl(Duration::from_secs(60));
+    server.queue(200, &[], "one");
+
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(server.requests().len(), 1);
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_offline() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("offline"), server.clone()).unwrap();
+    server.queue(200, &[("etag", "\"v1\"")], "one");
+
+    // stale entries are served when the network fails
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    let response = cache.send(&get(url)).unwrap();
+    assert_eq!(body(&response), "one");
+    assert!(response.header("warning").unwrap().starts_with("110"));
+    assert_eq!(server.requests().len(), 2);
+
+    // and without trying it at all in offline mode
+    cache.set_offline(true);
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert!(cache.send(&get("https://example.com/b")).is_err());
+    assert_eq!(server.requests().len(), 2);
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_rate_limit() {
+    let server = Arc::new(Server::default());
+    let cache = Arc::new(HttpCache::new(dir("rate-limit"), server.clone()).unwrap());
+    server.queue(200, &[("cache-control", "max-age=60")], "one");
+    let mut env = common::env();
+    env.transport = cache.clone();
+    net::set_rate_limit(&env, 2);
+    net::set_rate_limit_period(&env, 60);
+
+    let url = "https://example.com/a";
+    let send = |url: &str| {
+        let descriptor = net::init(&env, HttpMethod::Get as i32);
+        env.write_string(url, 0);
+        net::set_url(&env, descriptor, 0, url.len() as u32);
+        net::send(&env, descriptor);
+        net::get_status_code(&env, descriptor)
+    };
+    assert_eq!(send(url), 200);
+    // fresh entries and offline answers don't use up the limit
+    assert_eq!(send(url), 200);
+    cache.set_offline(true);
+    assert_eq!(send(url), 200);
+    assert_eq!(send("https://example.com/b"), -1);
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(env.rate_limiter.reserve(), Duration::ZERO);
+    assert!(env.rate_limiter.reserve() > Duration::ZERO);
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_age() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("age"), server.clone()).unwrap();
+    // already older than its max-age when it arrives
+    server.queue(
+        200,
+        &[("cache-control", "max-age=60"), ("age", "100")],
+        "one",
+    );
+    server.queue(
+        200,
+        &[("cache-control", "max-age=60"), ("age", "10")],
+        "two",
+    );
+
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "two");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "two");
+    assert_eq!(server.requests().len(), 2);
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_stale_on_error() {
+    let server = Arc::new(Server::default());
+    let cache = HttpCache::new(dir("stale-on-error"), server.clone()).unwrap();
+    let url = "https://example.com/a";
+
+    // error statuses are passed on
+    server.queue(200, &[("etag", "\"v1\"")], "one");
+    server.queue(503, &[], "down");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(cache.send(&get(url)).unwrap().status_code, 503);
+
+    // unless the entry allows standing in for them
+    server.queue(
+        200,
+        &[
+            ("cache-control", "max-age=0, stale-if-error=60"),
+            ("etag", "\"v2\""),
+        ],
+        "two",
+    );
+    server.queue(503, &[], "down");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "two");
+    let response = cache.send(&get(url)).unwrap();
+    assert_eq!(body(&response), "two");
+    assert!(response.header("warning").unwrap().starts_with("110"));
+
+    // entries that must be revalidated are never served stale
+    server.queue(
+        200,
+        &[("cache-control", "must-revalidate"), ("etag", "\"v3\"")],
+        "three",
+    );
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "three");
+    assert!(cache.send(&get(url)).is_err());
+    cache.set_offline(true);
+    assert!(cache.send(&get(url)).is_err());
+    _ = cache.clear();
+}
+
+#[test]
+pub fn test_replace_entry() {
+    let server = Arc::new(Server::default());
+    let dir = dir("replace");
+    let cache = HttpCache::new(&dir, server.clone()).unwrap();
+    server.queue(200, &[("etag", "\"v1\"")], "one");
+    server.queue(200, &[("etag", "\"v2\"")], "two");
+
+    let url = "https://example.com/a";
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "one");
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "two");
+    // the old body is gone, and no temporary files are left behind
+    let mut files: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(files.len(), 2);
+    assert!(files[0].ends_with(".body"));
+    assert!(files[1].ends_with(".json"));
+
+    cache.set_offline(true);
+    assert_eq!(body(&cache.send(&get(url)).unwrap()), "two");
+    _ = cache.clear();
+}
@@ -0,0 +1,155 @@
+use aidoku_runner::wasm::charset;
+use aidoku_runner::wasm::env::{HttpMethod, Request, Response, WasmEnv, WasmObject};
+use aidoku_runner::wasm::imports::{html, json, net};
+use aidoku_runner::wasm::transport::HttpTransport;
+use std::sync::Arc;
+
+mod common;
+use common::env;
+
+// 日本語 in Shift_JIS
+const SHIFT_JIS: &[u8] = &[0x93, 0xfa, 0x96, 0x7b, 0x8c, 0xea];
+// 한국어 in EUC-KR
+const EUC_KR: &[u8] = &[0xc7, 0xd1, 0xb1, 0xb9, 0xbe, 0xee];
+
+fn string(env: &WasmEnv, descriptor: i32) -> Option<String> {
+    match env.store().read_value(descriptor).as_deref() {
+        Some(WasmObject::String(str)) => Some(str.clone()),
+        _ => None,
+    }
+}
+
+struct Static {
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl HttpTransport for Static {
+    fn send(&self, request: &Request) -> anyhow::Result<Response> {
+        Ok(Response {
+            status_code: 200,
+            headers: vec![(String::from("content-type"), self.content_type.to_string())],
+            url: request.url.clone(),
+            data: self.body.clone(),
+            timings: None,
+        })
+    }
+}
+
+fn send(env: &WasmEnv) -> i32 {
+    let url = "https://example.com/";
+    let descriptor = net::init(env, HttpMethod::Get as i32);
+    env.write_string(url, 0);
+    net::set_url(env, descriptor, 0, url.len() as u32);
+    net::send(env, descriptor);
+    descriptor
+}
+
+#[test]
+pub fn test_decode() {
+    assert_eq!(
+        charset::decode(SHIFT_JIS, Some("text/html; charset=Shift_JIS")),
+        "日本語"
+    );
+    assert_eq!(
+        charset::decode(EUC_KR, Some("text/plain; charset=\"euc-kr\"")),
+        "한국어"
+    );
+
+    let mut page = b"<html><head><meta charset=\"EUC-KR\"></head><body>".to_vec();
+    page.extend_from_slice(EUC_KR);
+    assert!(charset::decode(&page, Some("text/html")).ends_with("한국어"));
+
+    let mut page =
+        b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=shift_jis\">".to_vec();
+    page.extend_from_slice(SHIFT_JIS);
+    assert!(charset::decode(&page, None).ends_with("日本語"));
+
+    // a byte order mark beats everything else
+    let mut bom = vec![0xef, 0xbb, 0xbf];
+    bom.extend_from_slice("한국어".as_bytes());
+    assert_eq!(
+        charset::decode(&bom, Some("text/html; charset=euc-kr")),
+        "한국어"
+    );
+    assert_eq!(charset::decode(&[0xff, 0xfe, 0x41, 0x00], None), "A");
+
+    // undeclared, but it isn't UTF-8
+    assert_eq!(charset::decode(SHIFT_JIS, None), "日本語");
+    assert_eq!(charset::decode("plain".as_bytes(), None), "plain");
+}
+
+#[test]
+pub fn test_detect_undeclared() {
+    // each of these decodes without errors in some of the others too
+    let texts = [
+        (encoding_rs::GBK, "中文漫画第一话更新了"),
+        (encoding_rs::GBK, "第12话 我的英雄学院"),
+        (encoding_rs::EUC_KR, "나 혼자만 레벨업 제 1화"),
+        (encoding_rs::EUC_KR, "한국어 웹툰을 읽어 보세요"),
+        (encoding_rs::BIG5, "中文漫畫第一話更新了"),
+        (encoding_rs::BIG5, "我的英雄學院 第12話"),
+        (encoding_rs::SHIFT_JIS, "進撃の巨人 第1話 二千年後の君へ"),
+        (encoding_rs::EUC_JP, "ワンピース 第1話 ロマンスドーン"),
+        (encoding_rs::EUC_JP, "日本語の漫画を読む"),
+    ];
+    for (encoding, text) in texts {
+        let (data, _, unmappable) = encoding.encode(text);
+        assert!(!unmappable);
+        assert_eq!(
+            charset::detect(&data, None).name(),
+            encoding.name(),
+            "{}",
+            text
+        );
+        assert_eq!(charset::decode(&data, Some("text/html")), text);
+    }
+}
+
+#[test]
+pub fn test_json_ignores_markup_declarations() {
+    let data = r#"{"url": "https://example.com/?charset=euc-kr", "title": "café"}"#.as_bytes();
+    assert_eq!(
+        charset::detect(data, Some("application/json")).name(),
+        "UTF-8"
+    );
+}
+
+#[test]
+pub fn test_imports() {
+    let mut env = env();
+    let mut body = b"<p>".to_vec();
+    body.extend_from_slice(SHIFT_JIS);
+    env.transport = Arc::new(Static {
+        content_type: "text/html; charset=Shift_JIS",
+        body,
+    });
+    let document = net::html(&env, send(&env));
+    let text = html::text(&env, document);
+    assert_eq!(string(&env, text).as_deref(), Some("日本語"));
+
+    let mut body = br#"{"title": ""#.to_vec();
+    body.extend_from_slice(EUC_KR);
+    body.extend_from_slice(br#""}"#);
+    env.transport = Arc::new(Static {
+        content_type: "application/json; charset=EUC-KR",
+        body,
+    });
+    let object = net::json(&env, send(&env));
+    assert!(matches!(
+        env.store().read_value(object).as_deref(),
+        Some(WasmObject::Object(map)) if matches!(&map["title"], WasmObject::String(title) if title == "한국어")
+    ));
+
+    // bytes handed to the parsers directly are decoded the same way
+    let mut data = b"<html><head><meta charset=\"shift_jis\"></head><body>".to_vec();
+    data.extend_from_slice(SHIFT_JIS);
+    env.write_bytes(&data, 0);
+    let document = html::parse(&env, 0, data.len() as u32);
+    let text = html::text(&env, document);
+    assert_eq!(string(&env, text).as_deref(), Some("日本語"));
+
+    let data = [&[0xef, 0xbb, 0xbf][..], b"[1]"].concat();
+    env.write_bytes(&data, 0);
+    assert!(json::parse(&env, 0, data.len() as u32) >= 0);
+}
@@ -0,0 +1,11 @@
+use aidoku_runner::wasm::env::WasmEnv;
+use aidoku_runner::AidokuSource;
+
+// an env backed by a real instance memory, like the ones imports receive
+pub fn env() -> WasmEnv {
+    let source = AidokuSource::from_bytes(br#"(module (memory (export "memory") 1))"#);
+    let mut env = source.env.clone();
+    let memory = source.instance.exports.get_memory("memory").unwrap();
+    env.memory.initialize(memory.clone());
+    env
+}
@@ -0,0 +1,159 @@
+use aidoku_runner::wasm::cookies::{Cookie, CookieJar};
+use aidoku_runner::wasm::env::WasmEnv;
+use std::sync::Arc;
+use url::Url;
+
+fn url(str: &str) -> Url {
+    Url::parse(str).unwrap()
+}
+
+#[test]
+pub fn test_parse() {
+    let cookie = Cookie::parse(
+        "id=a3fWa; Expires=Wed, 21-Oct-2015 07:28:00 GMT; Domain=.example.com; Path=/docs; Secure; HttpOnly",
+        &url("https://www.example.com/"),
+    )
+    .unwrap();
+    assert_eq!(cookie.name, "id");
+    assert_eq!(cookie.value, "a3fWa");
+    assert_eq!(cookie.domain, "example.com");
+    assert!(!cookie.host_only);
+    assert_eq!(cookie.path, "/docs");
+    assert_eq!(cookie.expires, Some(1445412480));
+    assert!(cookie.secure && cookie.http_only);
+
+    let cookie = Cookie::parse("a=b", &url("http://example.com/dir/page")).unwrap();
+    assert!(cookie.host_only);
+    assert_eq!(cookie.path, "/dir");
+    assert_eq!(cookie.expires, None);
+
+    // a response can't set cookies for another site
+    assert!(Cookie::parse("a=b; Domain=other.com", &url("http://example.com/")).is_none());
+}
+
+#[test]
+pub fn test_public_suffix_domain() {
+    // cookies can't be shared across a whole public suffix
+    assert!(Cookie::parse("a=b; Domain=com", &url("https://example.com/")).is_none());
+    assert!(Cookie::parse("a=b; Domain=co.uk", &url("https://www.example.co.uk/")).is_none());
+    assert!(Cookie::parse("a=b; Domain=github.io", &url("https://a.github.io/")).is_none());
+    let cookie = Cookie::parse(
+        "a=b; Domain=example.co.uk",
+        &url("https://www.example.co.uk/"),
+    )
+    .unwrap();
+    assert_eq!(cookie.domain, "example.co.uk");
+    assert!(!cookie.host_only);
+
+    // a host that is itself a public suffix keeps the cookie to itself
+    let cookie = Cookie::parse("a=b; Domain=github.io", &url("https://github.io/")).unwrap();
+    assert_eq!(cookie.domain, "github.io");
+    assert!(cookie.host_only);
+    assert!(!cookie.matches(&url("https://a.github.io/")));
+}
+
+#[test]
+pub fn test_ip_domain() {
+    assert!(Cookie::parse("a=b; Domain=0.0.1", &url("http://127.0.0.1/")).is_none());
+    let cookie = Cookie::parse("a=b; Domain=127.0.0.1", &url("http://127.0.0.1/")).unwrap();
+    assert_eq!(cookie.domain, "127.0.0.1");
+}
+
+#[test]
+pub fn test_header() {
+    let jar = CookieJar::new();
+    jar.set_cookies(
+        &url("https://example.com/manga/1"),
+        [
+            "session=1; Path=/",
+            "seen=yes; Path=/manga",
+            "secure=1; Path=/; Secure",
+            "wide=1; Domain=example.com; Path=/",
+        ],
+    );
+    assert_eq!(
+        jar.header(&url("https://example.com/manga/2")).as_deref(),
+        Some("seen=yes; session=1; secure=1; wide=1")
+    );
+    assert_eq!(
+        jar.header(&url("http://example.com/")).as_deref(),
+        Some("session=1; wide=1")
+    );
+    assert_eq!(
+        jar.header(&url("https://cdn.example.com/")).as_deref(),
+        Some("wide=1")
+    );
+    assert_eq!(jar.header(&url("https://example.org/")), None);
+}
+
+#[test]
+pub fn test_secure() {
+    // a plain http response can't set a secure cookie
+    assert!(Cookie::parse("a=b; Secure", &url("http://example.com/")).is_none());
+    let jar = CookieJar::new();
+    jar.set_cookies(&url("http://example.com/"), ["a=b; Secure", "c=d"]);
+    assert_eq!(jar.cookies().len(), 1);
+
+    // and one set over https isn't sent back over http
+    let cookie = Cookie::parse("a=b; Secure", &url("https://example.com/")).unwrap();
+    assert!(cookie.matches(&url("https://example.com/")));
+    assert!(!cookie.matches(&url("http://example.com/")));
+}
+
+#[test]
+pub fn test_expiry() {
+    let jar = CookieJar::new();
+    let page = url("https://example.com/");
+    jar.set_cookies(&page, ["a=1", "b=2; Max-Age=3600"]);
+    assert_eq!(jar.cookies().len(), 2);
+    jar.set_cookies(&page, ["a=; Max-Age=0"]);
+    assert_eq!(jar.header(&page).as_deref(), Some("b=2"));
+    jar.set_cookies(&page, ["b=; Expires=Thu, 01 Jan 1970 00:00:00 GMT"]);
+    assert!(jar.cookies().is_empty());
+}
+
+#[test]
+pub fn test_host_edits() {
+    let jar = CookieJar::new();
+    let page = url("https://example.com/");
+    jar.set_cookie(Cookie {
+        name: String::from("age_gate"),
+        value: String::from("1"),
+        domain: String::from("example.com"),
+        host_only: true,
+        path: String::from("/"),
+        expires: None,
+        secure: false,
+        http_only: false,
+    });
+    assert_eq!(jar.header(&page).as_deref(), Some("age_gate=1"));
+    jar.remove("example.com", "age_gate");
+    assert_eq!(jar.header(&page), None);
+    jar.set_cookies(&page, ["a=1"]);
+    jar.clear();
+    assert!(jar.cookies().is_empty());
+}
+
+#[test]
+pub fn test_persistence() {
+    let path = std::env::temp_dir().join(format!("aidoku-cookies-{}.json", std::process::id()));
+    _ = std::fs::remove_file(&path);
+    let page = url("https://example.com/");
+
+    let jar = CookieJar::new();
+    jar.persist_to(&path).unwrap();
+    jar.set_cookies(&page, ["login=token; Max-Age=3600"]);
+
+    let restored = CookieJar::new();
+    restored.persist_to(&path).unwrap();
+    assert_eq!(restored.header(&page).as_deref(), Some("login=token"));
+    _ = std::fs::remove_file(&path);
+}
+
+#[test]
+pub fn test_shared_between_instances() {
+    let first = WasmEnv::for_source("test.cookies");
+    let second = WasmEnv::for_source("test.cookies");
+    assert!(Arc::ptr_eq(&first.cookies, &second.cookies));
+    assert!(!Arc::ptr_eq(&WasmEnv::new().cookies, &first.cookies));
+}
@@ -0,0 +1,166 @@
+use aidoku_runner::wasm::date::parse_date;
+
+const OCT_20_2022: f64 = 1666224000.0;
+
+#[test]
+pub fn test_numeric_patterns() {
+    assert_eq!(
+        parse_date("2022-10-20", "yyyy-MM-dd", "", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20221020", "yyyyMMdd", "", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20.10.22", "dd.MM.yy", "", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("2022-293", "yyyy-DDD", "", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(parse_date("2022-10-20", "dd/MM/yyyy", "", ""), None);
+    assert_eq!(
+        parse_date("2022-10-20 trailing", "yyyy-MM-dd", "", ""),
+        None
+    );
+}
+
+#[test]
+pub fn test_literals() {
+    assert_eq!(
+        parse_date("2022-10-20T00:00:00", "yyyy-MM-dd'T'HH:mm:ss", "", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("o'clock 20 Oct 2022", "'o''clock' d MMM yyyy", "", ""),
+        Some(OCT_20_2022)
+    );
+}
+
+#[test]
+pub fn test_times() {
+    assert_eq!(
+        parse_date("2022-10-20 12:30:45.250", "yyyy-MM-dd HH:mm:ss.SSS", "", ""),
+        Some(OCT_20_2022 + 45045.25)
+    );
+    // more fraction digits than nanoseconds hold
+    assert_eq!(
+        parse_date(
+            "2022102012302500000000045",
+            "yyyyMMddHHmmSSSSSSSSSSSss",
+            "",
+            ""
+        ),
+        Some(OCT_20_2022 + 45045.25)
+    );
+    assert_eq!(
+        parse_date("10/20/2022 3:05 PM", "MM/dd/yyyy h:mm a", "en_US", ""),
+        Some(OCT_20_2022 + 54300.0)
+    );
+    assert_eq!(
+        parse_date("10/20/2022 12:05 am", "MM/dd/yyyy hh:mm a", "en_US", ""),
+        Some(OCT_20_2022 + 300.0)
+    );
+}
+
+#[test]
+pub fn test_offsets() {
+    assert_eq!(
+        parse_date("2022-10-20T12:30:45+0200", "yyyy-MM-dd'T'HH:mm:ssZ", "", ""),
+        Some(OCT_20_2022 + 37845.0)
+    );
+    assert_eq!(
+        parse_date("2022-10-20T12:30:45Z", "yyyy-MM-dd'T'HH:mm:ssXXX", "", ""),
+        Some(OCT_20_2022 + 45045.0)
+    );
+    assert_eq!(
+        parse_date(
+            "2022-10-20T12:30:45-03:00",
+            "yyyy-MM-dd'T'HH:mm:ssXXX",
+            "",
+            ""
+        ),
+        Some(OCT_20_2022 + 55845.0)
+    );
+    assert_eq!(
+        parse_date("20 Oct 2022 09:00 JST", "dd MMM yyyy HH:mm z", "", ""),
+        Some(OCT_20_2022)
+    );
+}
+
+#[test]
+pub fn test_timezones() {
+    assert_eq!(
+        parse_date(
+            "10/20/2022 3:05 PM",
+            "MM/dd/yyyy h:mm a",
+            "en_US",
+            "America/New_York"
+        ),
+        Some(OCT_20_2022 + 68700.0)
+    );
+    assert_eq!(
+        parse_date("2022-10-20 09:00", "yyyy-MM-dd HH:mm", "", "Asia/Tokyo"),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("2022-10-20 09:00", "yyyy-MM-dd HH:mm", "", "GMT+9"),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("2022-10-20", "yyyy-MM-dd", "", "Not/AZone"),
+        None
+    );
+}
+
+#[test]
+pub fn test_localized_names() {
+    assert_eq!(
+        parse_date("Oct 20, 2022", "MMM d, yyyy", "en_US", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("Thursday, October 20, 2022", "EEEE, MMMM d, yyyy", "en", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date(
+            "20 de octubre de 2022",
+            "d 'de' MMMM 'de' yyyy",
+            "es_ES",
+            ""
+        ),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20 oct. 2022", "d MMM yyyy", "es", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20 oct. 2022", "d MMM. yyyy", "es", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20 октября 2022", "d MMMM yyyy", "ru", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20. Oktober 2022", "d. MMMM yyyy", "de-DE", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("2022年10月20日", "yyyy年M月d日", "ja", ""),
+        Some(OCT_20_2022)
+    );
+    assert_eq!(
+        parse_date("20 Ekim 2022", "d MMMM yyyy", "tr", ""),
+        Some(OCT_20_2022)
+    );
+    // English names are accepted regardless of locale
+    assert_eq!(
+        parse_date("20 October 2022", "d MMMM yyyy", "pt_BR", ""),
+        Some(OCT_20_2022)
+    );
+}
@@ -0,0 +1,165 @@
+use aidoku_runner::wasm::allowlist::Allowlist;
+use aidoku_runner::wasm::config::{NetworkConfig, RetryPolicy};
+use aidoku_runner::wasm::env::{HttpMethod, Request, Response, WasmEnv};
+use aidoku_runner::wasm::har::HarRecorder;
+use aidoku_runner::wasm::imports::net;
+use aidoku_runner::wasm::transport::{HttpTransport, ReqwestTransport};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+mod common;
+
+struct Echo;
+
+impl HttpTransport for Echo {
+    fn send(&self, request: &Request) -> anyhow::Result<Response> {
+        let url = request.url.clone().unwrap();
+        if url.contains("fail") {
+            anyhow::bail!("connection refused");
+        }
+        Ok(Response {
+            status_code: 200,
+            headers: vec![
+                (String::from("content-type"), String::from("text/html")),
+                (String::from("set-cookie"), String::from("a=1; Path=/")),
+            ],
+            url: Some(url),
+            data: b"<html>hello</html>".to_vec(),
+            timings: None,
+        })
+    }
+}
+
+fn env() -> WasmEnv {
+    let mut env = common::env();
+    // one entry per request, even for the one that fails
+    env.network = Arc::new(NetworkConfig {
+        retry: RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        },
+        ..NetworkConfig::default()
+    });
+    env
+}
+
+fn send(env: &WasmEnv, method: HttpMethod, url: &str, body: Option<&str>) {
+    let descriptor = net::init(env, method as i32);
+    env.write_string(url, 0);
+    net::set_url(env, descriptor, 0, url.len() as u32);
+    env.write_string("X-Test", 1024);
+    env.write_string("yes", 1100);
+    net::set_header(env, descriptor, 1024, 6, 1100, 3);
+    if let Some(body) = body {
+        env.write_string(body, 2048);
+        net::set_body(env, descriptor, 2048, body.len() as u32);
+    }
+    net::send(env, descriptor);
+}
+
+#[test]
+pub fn test_har() {
+    let mut env = env();
+    let recorder = Arc::new(HarRecorder::new(Arc::new(Echo)));
+    env.transport = recorder.clone();
+    send(
+        &env,
+        HttpMethod::Get,
+        "https://example.com/search?q=one",
+        None,
+    );
+    send(
+        &env,
+        HttpMethod::Post,
+        "https://example.com/login",
+        Some("user=me"),
+    );
+    send(&env, HttpMethod::Get, "https://example.com/fail", None);
+
+    let har = recorder.har();
+    assert_eq!(har["log"]["version"], "1.2");
+    let entries = har["log"]["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 3);
+
+    let first = &entries[0];
+    assert_eq!(first["request"]["method"], "GET");
+    assert_eq!(first["request"]["queryString"][0]["value"], "one");
+    assert_eq!(first["request"]["headers"][0]["name"], "X-Test");
+    assert_eq!(first["response"]["status"], 200);
+    assert_eq!(first["response"]["statusText"], "OK");
+    assert_eq!(first["response"]["content"]["text"], "<html>hello</html>");
+    assert_eq!(first["response"]["cookies"][0]["name"], "a");
+    assert!(first["timings"]["wait"].as_f64().unwrap() >= 0.0);
+    assert_eq!(first["timings"]["comment"], "not measured by the transport");
+    assert!(first["startedDateTime"].as_str().unwrap().ends_with('Z'));
+
+    // the cookie from the first response is sent with the second request
+    let second = &entries[1];
+    assert_eq!(second["request"]["postData"]["text"], "user=me");
+    assert_eq!(second["request"]["cookies"][0]["value"], "1");
+
+    assert_eq!(entries[2]["response"]["status"], 0);
+    assert_eq!(entries[2]["response"]["_error"], "connection refused");
+}
+
+#[test]
+pub fn test_timings() {
+    // the headers come right away, the body a while later
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 4096];
+        _ = stream.read(&mut buf);
+        _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 4\r\n\r\n");
+        _ = stream.flush();
+        thread::sleep(Duration::from_millis(300));
+        _ = stream.write_all(b"body");
+    });
+
+    let mut env = env();
+    env.allowlist = Arc::new(Allowlist::new().allow("127.0.0.1"));
+    let recorder = Arc::new(HarRecorder::new(Arc::new(ReqwestTransport::new())));
+    env.transport = recorder.clone();
+    send(&env, HttpMethod::Get, &url, None);
+    server.join().unwrap();
+
+    let har = recorder.har();
+    let entry = &har["log"]["entries"][0];
+    assert_eq!(entry["response"]["content"]["text"], "body");
+    let timings = &entry["timings"];
+    let wait = timings["wait"].as_f64().unwrap();
+    let receive = timings["receive"].as_f64().unwrap();
+    assert!(receive >= 250.0, "receive was {}", receive);
+    assert!(wait < 250.0, "wait was {}", wait);
+    assert!((wait + receive - entry["time"].as_f64().unwrap()).abs() < 1e-6);
+    assert!(timings.get("comment").is_none());
+}
+
+#[test]
+pub fn test_body_limit() {
+    let mut env = env();
+    let recorder = Arc::new(HarRecorder::new(Arc::new(Echo)).with_body_limit(Some(6)));
+    env.transport = recorder.clone();
+    send(&env, HttpMethod::Get, "https://example.com/", None);
+
+    let har = recorder.har();
+    let content = &har["log"]["entries"][0]["response"]["content"];
+    assert_eq!(content["text"], "<html>");
+    assert_eq!(content["size"], 18);
+    assert_eq!(content["comment"], "truncated from 18 bytes");
+}
+
+#[test]
+pub fn test_write() {
+    let path = std::env::temp_dir().join(format!("aidoku-{}.har", std::process::id()));
+    let recorder = HarRecorder::new(Arc::new(Echo));
+    recorder.write(&path).unwrap();
+    let written: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(written["log"]["entries"].as_array().unwrap().len(), 0);
+    _ = std::fs::remove_file(&path);
+}
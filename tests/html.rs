@@ -0,0 +1,204 @@
+use aidoku_runner::wasm::env::{WasmEnv, WasmObject};
+use aidoku_runner::wasm::imports::{html, std};
+
+mod common;
+use common::env;
+
+const PAGE: &str = r#"<html><head><title>Test</title><script>var x = 1;</script></head>
+<body>
+  <div id="list" class="manga list">
+    <a class="item" href="/manga/1">One <b>bold</b></a>
+    <a class="item" href="manga/2">Two</a>
+    <p>Line<br>break</p>
+  </div>
+</body></html>"#;
+
+fn write(env: &WasmEnv, str: &str, offset: u32) -> (u32, u32) {
+    env.write_string(str, offset);
+    (offset, str.len() as u32)
+}
+
+fn parse(env: &WasmEnv, str: &str) -> i32 {
+    let (ptr, len) = write(env, str, 0);
+    let (uri, uri_len) = write(env, "https://example.com/dir/page", 4096);
+    html::parse_with_uri(env, ptr, len, uri, uri_len)
+}
+
+fn select(env: &WasmEnv, descriptor: i32, selector: &str) -> i32 {
+    let (ptr, len) = write(env, selector, 0);
+    html::select(env, descriptor, ptr, len)
+}
+
+fn string(env: &WasmEnv, descriptor: i32) -> String {
+    match env.store().read_value(descriptor).as_deref() {
+        Some(WasmObject::String(str)) => str.clone(),
+        _ => panic!("{} is not a string", descriptor),
+    }
+}
+
+fn attr(env: &WasmEnv, descriptor: i32, key: &str) -> String {
+    let (ptr, len) = write(env, key, 0);
+    string(env, html::attr(env, descriptor, ptr, len))
+}
+
+#[test]
+pub fn test_parse() {
+    let env = &env();
+    let document = parse(env, PAGE);
+    assert!(matches!(
+        env.store().read_value(document).as_deref(),
+        Some(WasmObject::Node(_))
+    ));
+    assert_eq!(
+        string(env, html::base_uri(env, document)),
+        "https://example.com/dir/page"
+    );
+    let (ptr, len) = write(env, "<p>fragment</p>", 0);
+    let fragment = html::parse_fragment(env, ptr, len);
+    assert_eq!(string(env, html::html(env, fragment)), "<p>fragment</p>");
+}
+
+#[test]
+pub fn test_select() {
+    let env = &env();
+    let document = parse(env, PAGE);
+    let items = select(env, document, "a.item");
+    assert_eq!(string(env, html::text(env, items)), "One bold Two");
+    let list = select(env, document, "#list");
+    assert_eq!(string(env, html::text(env, select(env, list, "b"))), "bold");
+    assert_eq!(select(env, document, "a[["), -1);
+}
+
+#[test]
+pub fn test_select_scope() {
+    let env = &env();
+    let document = parse(env, PAGE);
+    // nested selected nodes don't produce the same match twice
+    let nested = select(env, document, "div, a");
+    assert_eq!(
+        string(env, html::text(env, select(env, nested, "b"))),
+        "bold"
+    );
+    let links = html::array(env, select(env, nested, "a"));
+    assert_eq!(std::array_len(env, links), 2);
+
+    // matches come back in document order whatever order the scope is in
+    let items = select(env, document, "a");
+    let reversed = match env.store().read_value(items).as_deref() {
+        Some(WasmObject::Node(node)) => {
+            let mut node = node.clone();
+            node.nodes.reverse();
+            node
+        }
+        _ => panic!("not a node"),
+    };
+    let reversed = env.store().store_value(WasmObject::Node(reversed), None);
+    assert_eq!(string(env, html::text(env, reversed)), "Two One bold");
+    assert_eq!(
+        string(env, html::text(env, select(env, reversed, "a"))),
+        "One bold Two"
+    );
+}
+
+#[test]
+pub fn test_select_survives_document() {
+    let env = &env();
+    let document = parse(env, PAGE);
+    let items = select(env, document, "a");
+    env.store().remove_value(document);
+    assert_eq!(string(env, html::text(env, html::last(env, items))), "Two");
+}
+
+#[test]
+pub fn test_attr() {
+    let env = &env();
+    let items = select(env, parse(env, PAGE), "a");
+    assert_eq!(attr(env, items, "href"), "/manga/1");
+    assert_eq!(attr(env, items, "abs:href"), "https://example.com/manga/1");
+    assert_eq!(attr(env, items, "missing"), "");
+    let (ptr, len) = write(env, "href", 0);
+    let last = html::last(env, items);
+    assert_eq!(
+        string(env, html::abs_url(env, last, ptr, len)),
+        "https://example.com/dir/manga/2"
+    );
+    assert_eq!(html::has_attr(env, last, ptr, len), 1);
+}
+
+#[test]
+pub fn test_text() {
+    let env = &env();
+    let document = parse(env, PAGE);
+    let first = html::first(env, select(env, document, "a"));
+    assert_eq!(string(env, html::own_text(env, first)), "One");
+    let p = select(env, document, "p");
+    assert_eq!(string(env, html::text(env, p)), "Line break");
+    assert_eq!(string(env, html::untrimmed_text(env, p)), "Line\nbreak");
+    assert_eq!(
+        string(env, html::data(env, select(env, document, "script"))),
+        "var x = 1;"
+    );
+    assert!(!string(env, html::text(env, document)).contains("var x"));
+}
+
+#[test]
+pub fn test_html() {
+    let env = &env();
+    let b = select(env, parse(env, PAGE), "b");
+    assert_eq!(string(env, html::html(env, b)), "bold");
+    assert_eq!(string(env, html::outer_html(env, b)), "<b>bold</b>");
+}
+
+#[test]
+pub fn test_traversal() {
+    let env = &env();
+    let document = parse(env, PAGE);
+    let items = select(env, document, "a");
+    let next = html::next(env, html::first(env, items));
+    assert_eq!(attr(env, next, "href"), "manga/2");
+    let previous = html::previous(env, next);
+    assert_eq!(attr(env, previous, "href"), "/manga/1");
+    assert_eq!(html::previous(env, previous), -1);
+    let body = html::body(env, document);
+    assert_eq!(string(env, html::tag_name(env, body)), "body");
+    let array = html::array(env, items);
+    let len = match env.store().read_value(array).as_deref() {
+        Some(WasmObject::Array(arr)) => arr.len(),
+        _ => 0,
+    };
+    assert_eq!(len, 2);
+}
+
+#[test]
+pub fn test_node_info() {
+    let env = &env();
+    let list = select(env, parse(env, PAGE), "div");
+    assert_eq!(string(env, html::id(env, list)), "list");
+    assert_eq!(string(env, html::tag_name(env, list)), "div");
+    assert_eq!(string(env, html::class_name(env, list)), "manga list");
+    let (ptr, len) = write(env, "manga", 0);
+    assert_eq!(html::has_class(env, list, ptr, len), 1);
+    let (ptr, len) = write(env, "man", 0);
+    assert_eq!(html::has_class(env, list, ptr, len), 0);
+}
+
+#[test]
+pub fn test_escape() {
+    let env = &env();
+    let descriptor = env
+        .store()
+        .store_value(WasmObject::String("<a href=\"x\">&</a>".into()), None);
+    let escaped = html::escape(env, descriptor);
+    assert_eq!(
+        string(env, escaped),
+        "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+    );
+    assert_eq!(
+        string(env, html::unescape(env, escaped)),
+        "<a href=\"x\">&</a>"
+    );
+    assert_eq!(
+        html::unescape_str("&#39;&#x41;&eacute &copy; &bogus;"),
+        "'Aé © &bogus;"
+    );
+}
@@ -0,0 +1,134 @@
+use aidoku_runner::wasm::env::{Request, Response};
+use aidoku_runner::wasm::imports::net;
+use aidoku_runner::wasm::interceptor::{
+    detect_challenge, Action, Challenge, Exchange, Interceptor,
+};
+use aidoku_runner::wasm::transport::HttpTransport;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+mod common;
+use common::env;
+
+fn challenge_page() -> Response {
+    Response {
+        status_code: 503,
+        headers: vec![
+            (String::from("server"), String::from("cloudflare")),
+            (String::from("content-type"), String::from("text/html")),
+        ],
+        url: None,
+        data: b"<html><head><title>Just a moment...</title></head></html>".to_vec(),
+        timings: None,
+    }
+}
+
+// Serves a challenge until the clearance cookie is sent.
+#[derive(Default)]
+struct Protected {
+    sent: Mutex<Vec<Request>>,
+}
+
+impl HttpTransport for Protected {
+    fn send(&self, request: &Request) -> anyhow::Result<Response> {
+        self.sent.lock().unwrap().push(request.clone());
+        if request.header("cookie") == Some("cf_clearance=solved") {
+            Ok(Response {
+                status_code: 200,
+                headers: Vec::new(),
+                url: request.url.clone(),
+                data: b"content".to_vec(),
+                timings: None,
+            })
+        } else {
+            Ok(challenge_page())
+        }
+    }
+}
+
+// Stands in for a host that solves challenges in a webview.
+#[derive(Default)]
+struct Solver {
+    challenges: Mutex<Vec<Challenge>>,
+}
+
+impl Interceptor for Solver {
+    fn request(&self, request: &mut Request) {
+        request.set_header("X-Intercepted", String::from("yes"));
+    }
+
+    fn response(&self, exchange: &Exchange) -> Action {
+        match exchange.challenge {
+            Some(challenge) => {
+                self.challenges.lock().unwrap().push(challenge);
+                let url = Url::parse(exchange.request.url.as_ref().unwrap()).unwrap();
+                exchange.cookies.set_cookies(&url, ["cf_clearance=solved"]);
+                Action::Retry
+            }
+            None => Action::Continue,
+        }
+    }
+}
+
+#[test]
+pub fn test_detect_challenge() {
+    assert_eq!(
+        detect_challenge(&challenge_page()),
+        Some(Challenge::Cloudflare)
+    );
+
+    let mut mitigated = challenge_page();
+    mitigated.data = Vec::new();
+    assert_eq!(detect_challenge(&mitigated), None);
+    mitigated
+        .headers
+        .push((String::from("cf-mitigated"), String::from("challenge")));
+    assert_eq!(detect_challenge(&mitigated), Some(Challenge::Cloudflare));
+
+    let mut ok = challenge_page();
+    ok.status_code = 200;
+    assert_eq!(detect_challenge(&ok), None);
+}
+
+#[test]
+pub fn test_interceptor() {
+    let mut env = env();
+    let transport = Arc::new(Protected::default());
+    let solver = Arc::new(Solver::default());
+    env.transport = transport.clone();
+    env.interceptor = Some(solver.clone());
+
+    let url = "https://example.com/";
+    let descriptor = net::init(&env, 0);
+    env.write_string(url, 0);
+    net::set_url(&env, descriptor, 0, url.len() as u32);
+    net::send(&env, descriptor);
+
+    assert_eq!(net::get_status_code(&env, descriptor), 200);
+    assert_eq!(
+        *solver.challenges.lock().unwrap(),
+        vec![Challenge::Cloudflare]
+    );
+    let sent = transport.sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert!(sent
+        .iter()
+        .all(|request| request.header("x-intercepted") == Some("yes")));
+}
+
+#[test]
+pub fn test_without_interceptor() {
+    let mut env = env();
+    let transport = Arc::new(Protected::default());
+    env.transport = transport.clone();
+
+    let url = "https://example.com/";
+    let descriptor = net::init(&env, 0);
+    env.write_string(url, 0);
+    net::set_url(&env, descriptor, 0, url.len() as u32);
+    net::send(&env, descriptor);
+
+    // the challenge page reaches the source, without being retried
+    assert_eq!(net::get_status_code(&env, descriptor), 503);
+    assert_eq!(transport.sent.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,194 @@
+use ::std::time::{Duration, Instant};
+use aidoku_runner::wasm::env::{WasmEnv, WasmObject};
+use aidoku_runner::wasm::imports::{aidoku, html, json, std};
+use indexmap::IndexMap;
+
+mod common;
+use common::env;
+
+fn parse(env: &WasmEnv, str: &str) -> i32 {
+    env.write_string(str, 0);
+    json::parse(env, 0, str.len() as u32)
+}
+
+fn stringify(env: &WasmEnv, obj: WasmObject) -> String {
+    let descriptor = env.store().store_value(obj, None);
+    let json = json::stringify(env, descriptor);
+    match env.store().read_value(json).as_deref() {
+        Some(WasmObject::String(str)) => str.clone(),
+        _ => panic!("stringify didn't return a string"),
+    }
+}
+
+#[test]
+pub fn test_parse() {
+    let env = &env();
+    let descriptor = parse(env, r#"{"a": [1, 2.5, "x", true, null]}"#);
+    assert!(matches!(
+        env.store().read_value(descriptor).as_deref(),
+        Some(WasmObject::Object(map)) if matches!(&map["a"], WasmObject::Array(arr) if arr.len() == 5)
+    ));
+
+    assert_eq!(parse(env, "<html><body>502 Bad Gateway</body></html>"), -1);
+    assert_eq!(parse(env, r#"{"a": "#), -1);
+    assert_eq!(env.store().value_count(), 1);
+}
+
+#[test]
+pub fn test_stringify() {
+    let env = &env();
+    assert_eq!(stringify(env, WasmObject::Int(1)), "1");
+    assert_eq!(stringify(env, WasmObject::Float(f64::NAN)), "null");
+    assert_eq!(
+        stringify(env, WasmObject::String(String::from("a\"b"))),
+        r#""a\"b""#
+    );
+    assert_eq!(
+        stringify(env, WasmObject::Date(1_000_000_000.5)),
+        r#""2001-09-09T01:46:40.500Z""#
+    );
+
+    let mut map = IndexMap::new();
+    map.insert(
+        String::from("list"),
+        WasmObject::Array(vec![WasmObject::Bool(true), WasmObject::Null]),
+    );
+    map.insert(String::from("page"), WasmObject::Int(2));
+    let json = stringify(env, WasmObject::Object(map));
+    assert_eq!(json, r#"{"list":[true,null],"page":2}"#);
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        value,
+        serde_json::json!({ "list": [true, null], "page": 2 })
+    );
+
+    // round trips through parse
+    let descriptor = parse(env, &json);
+    let again = json::stringify(env, descriptor);
+    assert!(matches!(
+        env.store().read_value(again).as_deref(),
+        Some(WasmObject::String(str)) if *str == json
+    ));
+
+    assert_eq!(json::stringify(env, 12345), -1);
+}
+
+#[test]
+pub fn test_lazy() {
+    let env = &env();
+    let descriptor = parse(
+        env,
+        r#"{"chapters": [{"id": "a", "num": 1}, {"id": "b", "num": 2.5}], "next": null}"#,
+    );
+    let key = |str: &str| {
+        env.write_string(str, 1000);
+        (1000, str.len() as u32)
+    };
+
+    // children point into the parsed document instead of copying out of it
+    let (ptr, len) = key("chapters");
+    let chapters = std::object_get(env, descriptor, ptr, len);
+    assert_eq!(std::array_len(env, chapters), 2);
+    assert_eq!(std::value_kind(env, chapters), 5);
+    let chapter = std::array_get(env, chapters, 1);
+    assert_eq!(std::object_len(env, chapter), 2);
+    let (ptr, len) = key("num");
+    let num = std::object_get(env, chapter, ptr, len);
+    assert_eq!(std::read_float(env, num), 2.5);
+    assert!(env.store().json_value(chapter).is_some());
+    assert_eq!(std::array_get(env, chapters, 2), -1);
+
+    // changing any part converts the document, and every descriptor into it
+    // sees the change, except those into the value that was replaced
+    std::object_set(env, chapter, ptr, len, std::create_int(env, 3));
+    assert!(env.store().json_value(descriptor).is_none());
+    assert!(env.store().json_value(chapter).is_none());
+    assert!(env.store().read_value(num).is_none());
+    let num = std::object_get(env, chapter, ptr, len);
+    assert_eq!(std::read_int(env, num), 3);
+    let json = json::stringify(env, descriptor);
+    assert!(matches!(
+        env.store().read_value(json).as_deref(),
+        Some(WasmObject::String(json)) if json.contains(r#"{"id":"b","num":3}"#)
+    ));
+
+    std::destroy(env, descriptor);
+    // the new int and the string are all that's left
+    assert_eq!(env.store().value_count(), 2);
+}
+
+#[test]
+pub fn test_lazy_reads() {
+    let env = &env();
+    let descriptor = parse(env, r#"{"id": "12", "num": 2.5, "tags": ["a", "b"]}"#);
+    let key = |str: &str| {
+        env.write_string(str, 1000);
+        (1000, str.len() as u32)
+    };
+
+    // reads coerce the json value without converting the rest of it
+    let (ptr, len) = key("id");
+    let id = std::object_get(env, descriptor, ptr, len);
+    assert_eq!(std::read_int(env, id), 12);
+    let (ptr, len) = key("num");
+    let num = std::object_get(env, descriptor, ptr, len);
+    assert_eq!(std::string_len(env, num), 3);
+    assert_eq!(std::read_bool(env, num), 1);
+    assert_eq!(std::read_int(env, descriptor), -1);
+    assert_eq!(std::read_date(env, descriptor), -1f64);
+
+    // copies and values stay unconverted
+    let copy = std::copy(env, descriptor);
+    let store = env.store();
+    assert_eq!(store.json_value(copy), store.json_value(descriptor));
+    drop(store);
+    let values = std::object_values(env, descriptor);
+    assert_eq!(
+        env.store().json_value(values),
+        Some(&serde_json::json!(["12", 2.5, ["a", "b"]]))
+    );
+    assert_eq!(std::array_len(env, std::array_get(env, values, 2)), 2);
+
+    // and changing a copy leaves the original alone
+    std::object_remove(env, copy, ptr, len);
+    assert_eq!(std::object_len(env, copy), 2);
+    assert_eq!(std::object_len(env, descriptor), 3);
+    assert!(env.store().json_value(descriptor).is_some());
+}
+
+#[test]
+pub fn test_large_reads() {
+    let env = &env();
+    let item = r#"{"id": "1", "title": "manga", "tags": ["a", "b", "c"]}"#;
+    let document = format!(r#"{{"list": [{}]}}"#, vec![item; 20_000].join(","));
+    // too big for the test memory, so it skips json::parse
+    let descriptor = env
+        .store()
+        .store_json(serde_json::from_str(&document).unwrap());
+    env.write_string("list", 0);
+    let list = std::object_get(env, descriptor, 0, 4);
+
+    let start = Instant::now();
+    drop(env.store().read_value(list));
+    let convert = start.elapsed();
+
+    // none of these need the list converted, so asking many times costs less
+    // than converting it a few times would
+    let start = Instant::now();
+    for _ in 0..100 {
+        assert_eq!(std::object_get(env, list, 0, 4), -1);
+        assert_eq!(std::value_kind(env, list), 5);
+        assert_eq!(std::array_len(env, list), 20_000);
+        assert_eq!(html::text(env, list), -1);
+        let result = aidoku::create_manga_result(env, list, 0);
+        std::destroy(env, result);
+    }
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed < convert * 10 + Duration::from_millis(50),
+        "100 reads took {:?}, converting once {:?}",
+        elapsed,
+        convert
+    );
+    assert!(env.store().json_value(descriptor).is_some());
+}
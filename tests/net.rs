@@ -0,0 +1,708 @@
+use aidoku_runner::wasm::allowlist::Allowlist;
+use aidoku_runner::wasm::cache::HttpCache;
+use aidoku_runner::wasm::cassette::Cassette;
+use aidoku_runner::wasm::config::{NetworkConfig, RedirectPolicy, RetryPolicy, DEFAULT_USER_AGENT};
+use aidoku_runner::wasm::env::{HttpMethod, Request, Response, WasmEnv, WasmObject};
+use aidoku_runner::wasm::har::HarRecorder;
+use aidoku_runner::wasm::imports::{html, net};
+use aidoku_runner::wasm::transport::{HttpTransport, NetErrorKind, ReqwestTransport};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+mod common;
+
+fn env() -> WasmEnv {
+    let mut env = common::env();
+    // the test servers run on loopback
+    env.allowlist = Arc::new(Allowlist::new().allow("127.0.0.1"));
+    env
+}
+
+// Answers one connection per response, returning the raw requests it saw.
+fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let len = stream.read(&mut buf).unwrap_or(0);
+            requests.push(String::from_utf8_lossy(&buf[..len]).to_string());
+            _ = stream.write_all(response.as_bytes());
+        }
+        requests
+    });
+    (url, server)
+}
+
+fn response(status: &str, headers: &[&str], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for header in headers {
+        response.push_str(header);
+        response.push_str("\r\n");
+    }
+    response.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    response
+}
+
+fn request(env: &WasmEnv, url: &str) -> i32 {
+    let descriptor = net::init(env, HttpMethod::Get as i32);
+    env.write_string(url, 0);
+    net::set_url(env, descriptor, 0, url.len() as u32);
+    descriptor
+}
+
+fn string(env: &WasmEnv, descriptor: i32) -> Option<String> {
+    match env.store().read_value(descriptor).as_deref() {
+        Some(WasmObject::String(str)) => Some(str.clone()),
+        _ => None,
+    }
+}
+
+fn header(env: &WasmEnv, descriptor: i32, name: &str) -> Option<String> {
+    env.write_string(name, 0);
+    string(env, net::get_header(env, descriptor, 0, name.len() as u32))
+}
+
+#[test]
+pub fn test_response_metadata() {
+    let env = &env();
+    let (url, server) = serve(vec![
+        response("302 Found", &["Location: /final"], ""),
+        response(
+            "404 Not Found",
+            &["Set-Cookie: a=1", "Set-Cookie: b=2", "X-Test: yes"],
+            "missing",
+        ),
+    ]);
+    let descriptor = request(env, &format!("{}/start", url));
+    assert_eq!(net::get_status_code(env, descriptor), -1);
+    net::send(env, descriptor);
+    server.join().unwrap();
+
+    assert_eq!(net::get_status_code(env, descriptor), 404);
+    assert_eq!(header(env, descriptor, "x-test").as_deref(), Some("yes"));
+    assert_eq!(
+        header(env, descriptor, "Set-Cookie").as_deref(),
+        Some("a=1, b=2")
+    );
+    assert_eq!(header(env, descriptor, "missing"), None);
+    let res = env
+        .store()
+        .get_request(&descriptor)
+        .unwrap()
+        .response
+        .clone()
+        .unwrap();
+    assert_eq!(res.url, Some(format!("{}/final", url)));
+}
+
+#[test]
+pub fn test_cookies() {
+    let env = &env();
+    let (url, server) = serve(vec![
+        response(
+            "302 Found",
+            &["Location: /home", "Set-Cookie: session=abc; Path=/"],
+            "",
+        ),
+        response("200 OK", &[], ""),
+        response("200 OK", &[], ""),
+    ]);
+    net::send(env, request(env, &format!("{}/login", url)));
+    net::send(env, request(env, &format!("{}/other", url)));
+    let requests = server.join().unwrap();
+
+    assert!(!requests[0].to_lowercase().contains("cookie:"));
+    assert!(requests[1].contains("cookie: session=abc"));
+    assert!(requests[2].contains("cookie: session=abc"));
+}
+
+#[test]
+pub fn test_html() {
+    let env = &env();
+    let (url, server) = serve(vec![response("200 OK", &[], r#"<a href="next">Next</a>"#)]);
+    let descriptor = request(env, &format!("{}/dir/page", url));
+    net::send(env, descriptor);
+    server.join().unwrap();
+
+    let document = net::html(env, descriptor);
+    env.write_string("a", 0);
+    let link = html::select(env, document, 0, 1);
+    env.write_string("abs:href", 0);
+    let link = html::attr(env, link, 0, 8);
+    assert_eq!(string(env, link), Some(format!("{}/dir/next", url)));
+}
+
+#[test]
+pub fn test_get_image() {
+    let env = &env();
+    let (url, server) = serve(vec![response(
+        "200 OK",
+        &["Content-Type: image/png"],
+        "png",
+    )]);
+    let descriptor = request(env, &url);
+    assert_eq!(net::get_image(env, descriptor), -1);
+    net::send(env, descriptor);
+    server.join().unwrap();
+
+    let image = net::get_image(env, descriptor);
+    assert!(matches!(
+        env.store().read_value(image).as_deref(),
+        Some(WasmObject::Image(data)) if data == b"png"
+    ));
+}
+
+#[test]
+pub fn test_rate_limit() {
+    let env = &env();
+    net::set_rate_limit(env, 2);
+    net::set_rate_limit_period(env, 1);
+    let (url, server) = serve(vec![response("200 OK", &[], ""); 3]);
+    let start = Instant::now();
+    for _ in 0..3 {
+        net::send(env, request(env, &url));
+    }
+    server.join().unwrap();
+    assert!(start.elapsed() >= Duration::from_secs(1));
+}
+
+#[test]
+pub fn test_retry_delay() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(300),
+        ..RetryPolicy::default()
+    };
+    let delays: Vec<Duration> = (0..20).flat_map(|_| policy.delay(2, None)).collect();
+    assert_eq!(delays.len(), 20);
+    assert!(delays
+        .iter()
+        .all(|delay| (Duration::from_millis(100)..=Duration::from_millis(200)).contains(delay)));
+    // jittered, not the same every time
+    assert!(delays.iter().any(|delay| *delay != delays[0]));
+    // the backoff is capped before the jitter
+    assert!(policy
+        .delay(5, None)
+        .is_some_and(
+            |delay| (Duration::from_millis(150)..=Duration::from_millis(300)).contains(&delay)
+        ));
+    // Retry-After is waited out in full, not cut down to the backoff cap
+    assert_eq!(
+        policy.delay(1, Some(Duration::from_secs(5))),
+        Some(Duration::from_secs(5))
+    );
+    // and a wait past max_retry_after isn't waited at all
+    let policy = RetryPolicy {
+        max_retry_after: Duration::from_secs(10),
+        ..policy
+    };
+    assert_eq!(policy.delay(1, Some(Duration::from_secs(11))), None);
+}
+
+// Answers every request with a redirect to /done, then a 200.
+#[derive(Default)]
+struct MockTransport {
+    sent: Mutex<Vec<Request>>,
+}
+
+impl HttpTransport for MockTransport {
+    fn send(&self, request: &Request) -> anyhow::Result<Response> {
+        let mut sent = self.sent.lock().unwrap();
+        sent.push(request.clone());
+        let url = request.url.clone().unwrap();
+        let (status_code, headers) = if url.ends_with("/done") {
+            (200, Vec::new())
+        } else {
+            (303, vec![(String::from("location"), String::from("/done"))])
+        };
+        Ok(Response {
+            status_code,
+            headers,
+            url: Some(url),
+            data: b"mock".to_vec(),
+            timings: None,
+        })
+    }
+}
+
+#[test]
+pub fn test_transport() {
+    let mut env = env();
+    let transport = Arc::new(MockTransport::default());
+    env.transport = transport.clone();
+    let descriptor = net::init(&env, HttpMethod::Post as i32);
+    let url = "https://example.com/submit";
+    env.write_string(url, 0);
+    net::set_url(&env, descriptor, 0, url.len() as u32);
+    env.write_string("body", 0);
+    net::set_body(&env, descriptor, 0, 4);
+    net::send(&env, descriptor);
+
+    assert_eq!(net::get_status_code(&env, descriptor), 200);
+    let sent = transport.sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].method, HttpMethod::Post);
+    assert_eq!(sent[0].body.as_deref(), Some(&b"body"[..]));
+    assert_eq!(sent[1].method, HttpMethod::Get);
+    assert_eq!(sent[1].url.as_deref(), Some("https://example.com/done"));
+    assert_eq!(sent[1].body, None);
+}
+
+#[test]
+pub fn test_cassette() {
+    let path = std::env::temp_dir().join(format!("aidoku-cassette-{}.json", std::process::id()));
+    let (url, server) = serve(vec![
+        response("200 OK", &["X-Test: first"], "one"),
+        response("200 OK", &["X-Test: second"], "two"),
+    ]);
+
+    let mut env = env();
+    let recorder = Arc::new(Cassette::record(&path, Arc::new(ReqwestTransport::new())).unwrap());
+    env.transport = recorder.clone();
+    net::send(&env, request(&env, &url));
+    net::send(&env, request(&env, &url));
+    server.join().unwrap();
+    assert_eq!(recorder.interactions().len(), 2);
+
+    // the server is gone, so these can only come from the cassette
+    let player = Arc::new(Cassette::replay(&path).unwrap());
+    env.transport = player.clone();
+    let first = request(&env, &url);
+    net::send(&env, first);
+    let second = request(&env, &url);
+    net::send(&env, second);
+    assert_eq!(header(&env, first, "x-test").as_deref(), Some("first"));
+    assert_eq!(header(&env, second, "x-test").as_deref(), Some("second"));
+    assert!(player.unmatched().is_empty());
+
+    net::send(&env, request(&env, &format!("{}/other", url)));
+    assert_eq!(player.take_unmatched(), vec![format!("GET {}/other", url)]);
+    _ = std::fs::remove_file(&path);
+}
+
+#[test]
+pub fn test_network_config() {
+    let env = &env().with_network(NetworkConfig {
+        redirects: RedirectPolicy::None,
+        ..NetworkConfig::default()
+    });
+    let (url, server) = serve(vec![
+        response("302 Found", &["Location: /final"], ""),
+        response("200 OK", &[], ""),
+    ]);
+    let descriptor = request(env, &url);
+    net::send(env, descriptor);
+    assert_eq!(net::get_status_code(env, descriptor), 302);
+
+    let descriptor = request(env, &url);
+    env.write_string("User-Agent", 0);
+    env.write_string("source", 100);
+    net::set_header(env, descriptor, 0, 10, 100, 6);
+    net::send(env, descriptor);
+    let requests = server.join().unwrap();
+
+    let user_agent = format!("user-agent: {}\r\n", DEFAULT_USER_AGENT);
+    assert!(requests[0].contains(&user_agent));
+    assert!(requests[1].contains("user-agent: source\r\n"));
+}
+
+#[test]
+pub fn test_transport_error() {
+    let env = &env().with_network(NetworkConfig {
+        retry: RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        },
+        ..NetworkConfig::default()
+    });
+    // nothing listens on a port that was just released
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    let descriptor = request(env, &url);
+    net::send(env, descriptor);
+    assert_eq!(net::get_status_code(env, descriptor), -1);
+    assert_eq!(net::get_data_size(env, descriptor), -1);
+    assert_eq!(
+        net::error(env, descriptor).map(|err| err.kind),
+        Some(NetErrorKind::Connect)
+    );
+
+    let descriptor = request(env, "not a url");
+    net::send(env, descriptor);
+    assert_eq!(net::get_status_code(env, descriptor), -1);
+    assert_eq!(
+        net::error(env, descriptor).map(|err| err.kind),
+        Some(NetErrorKind::InvalidUrl)
+    );
+}
+
+// Answers with the queued statuses in order, then with 200.
+#[derive(Default)]
+struct Flaky {
+    statuses: Mutex<Vec<(i32, Option<&'static str>)>>,
+    sent: Mutex<usize>,
+}
+
+impl HttpTransport for Flaky {
+    fn send(&self, request: &Request) -> anyhow::Result<Response> {
+        *self.sent.lock().unwrap() += 1;
+        let mut statuses = self.statuses.lock().unwrap();
+        let (status_code, retry_after) = if statuses.is_empty() {
+            (200, None)
+        } else {
+            statuses.remove(0)
+        };
+        Ok(Response {
+            status_code,
+            headers: retry_after
+                .map(|value| vec![(String::from("retry-after"), value.to_string())])
+                .unwrap_or_default(),
+            url: request.url.clone(),
+            data: Vec::new(),
+            timings: None,
+        })
+    }
+}
+
+#[test]
+pub fn test_error_kinds() {
+    use aidoku_runner::wasm::transport::NetError;
+    use std::io;
+
+    // the outer message names the url, which says nothing about the failure
+    let err = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused))
+        .context("error sending request for url (https://timed-out.example/certificate)");
+    assert_eq!(NetError::from_error(&err).kind, NetErrorKind::Connect);
+    let err = anyhow::anyhow!("https://dns-error.example/ timed out");
+    assert_eq!(NetError::from_error(&err).kind, NetErrorKind::Other);
+
+    let err = anyhow::Error::new(io::Error::from(io::ErrorKind::TimedOut)).context("failed");
+    assert_eq!(NetError::from_error(&err).kind, NetErrorKind::Timeout);
+    let err = anyhow::Error::new(io::Error::other(
+        "failed to lookup address information: Name or service not known",
+    ))
+    .context("dns error")
+    .context("error sending request for url (https://example.com/)");
+    assert_eq!(NetError::from_error(&err).kind, NetErrorKind::Dns);
+
+    // a server that doesn't speak tls
+    let env = &env().with_network(NetworkConfig {
+        retry: RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        },
+        ..NetworkConfig::default()
+    });
+    let (url, server) = serve(vec![response("200 OK", &[], "")]);
+    let descriptor = request(env, &url.replace("http:", "https:"));
+    net::send(env, descriptor);
+    server.join().unwrap();
+    assert_eq!(
+        net::error(env, descriptor).map(|err| err.kind),
+        Some(NetErrorKind::Tls)
+    );
+}
+
+#[test]
+pub fn test_retry() {
+    let mut env = env();
+    env.network = Arc::new(NetworkConfig {
+        retry: RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        },
+        ..NetworkConfig::default()
+    });
+    let url = "https://example.com/";
+
+    let transport = Arc::new(Flaky::default());
+    *transport.statuses.lock().unwrap() = vec![(503, None), (502, None)];
+    env.transport = transport.clone();
+    let descriptor = request(&env, url);
+    net::send(&env, descriptor);
+    assert_eq!(net::get_status_code(&env, descriptor), 200);
+    assert_eq!(*transport.sent.lock().unwrap(), 3);
+
+    // gives up after max_attempts
+    let transport = Arc::new(Flaky::default());
+    *transport.statuses.lock().unwrap() = vec![(503, None); 3];
+    env.transport = transport.clone();
+    let descriptor = request(&env, url);
+    net::send(&env, descriptor);
+    assert_eq!(net::get_status_code(&env, descriptor), 503);
+    assert_eq!(*transport.sent.lock().unwrap(), 3);
+
+    // POST isn't retried by default
+    let transport = Arc::new(Flaky::default());
+    *transport.statuses.lock().unwrap() = vec![(503, None)];
+    env.transport = transport.clone();
+    let descriptor = net::init(&env, HttpMethod::Post as i32);
+    env.write_string(url, 0);
+    net::set_url(&env, descriptor, 0, url.len() as u32);
+    net::send(&env, descriptor);
+    assert_eq!(net::get_status_code(&env, descriptor), 503);
+    assert_eq!(*transport.sent.lock().unwrap(), 1);
+
+    let transport = Arc::new(Flaky::default());
+    *transport.statuses.lock().unwrap() = vec![(429, Some("1"))];
+    env.transport = transport.clone();
+    let start = Instant::now();
+    let descriptor = request(&env, url);
+    net::send(&env, descriptor);
+    assert_eq!(net::get_status_code(&env, descriptor), 200);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+
+    // a server asking for too long a wait gets its answer passed on
+    let transport = Arc::new(Flaky::default());
+    *transport.statuses.lock().unwrap() = vec![(429, Some("3600"))];
+    env.transport = transport.clone();
+    let start = Instant::now();
+    let descriptor = request(&env, url);
+    net::send(&env, descriptor);
+    assert_eq!(net::get_status_code(&env, descriptor), 429);
+    assert_eq!(*transport.sent.lock().unwrap(), 1);
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+pub fn test_allowlist() {
+    let mut env = env();
+    let transport = Arc::new(MockTransport::default());
+    env.transport = transport.clone();
+    env.allowlist = Arc::new(Allowlist::from_domains(["https://example.com"]));
+
+    // the redirect to /done stays on the same domain
+    let descriptor = request(&env, "https://www.example.com/start");
+    net::send(&env, descriptor);
+    assert_eq!(net::get_status_code(&env, descriptor), 200);
+
+    for url in [
+        "https://example.org/",
+        "http://127.0.0.1/",
+        "file:///etc/passwd",
+    ] {
+        let descriptor = request(&env, url);
+        net::send(&env, descriptor);
+        assert_eq!(net::get_status_code(&env, descriptor), -1);
+        assert_eq!(
+            net::error(&env, descriptor).map(|err| err.kind),
+            Some(NetErrorKind::Blocked)
+        );
+    }
+    assert_eq!(transport.sent.lock().unwrap().len(), 2);
+}
+
+#[test]
+pub fn test_pinned_addresses() {
+    let transport = ReqwestTransport::new();
+    let (url, server) = serve(vec![response("200 OK", &[], "pinned")]);
+    let address: std::net::SocketAddr = url.trim_start_matches("http://").parse().unwrap();
+
+    // the name never gets looked up, only the checked address is used
+    let mut req = Request {
+        method: HttpMethod::Get,
+        url: Some(format!("http://unresolvable.invalid:{}/", address.port())),
+        headers: Default::default(),
+        body: None,
+        response: None,
+        error: None,
+        addresses: Some(vec![address]),
+    };
+    let res = transport.send(&req).unwrap();
+    server.join().unwrap();
+    assert_eq!(res.data, b"pinned");
+
+    req.addresses = Some(Vec::new());
+    let err = transport.send(&req).unwrap_err();
+    assert_eq!(
+        aidoku_runner::wasm::transport::NetError::from_error(&err).kind,
+        NetErrorKind::Dns
+    );
+}
+
+#[test]
+pub fn test_body_limit() {
+    let mut env = env();
+    env.network = Arc::new(NetworkConfig {
+        max_body_size: Some(10),
+        ..NetworkConfig::default()
+    });
+    env.transport = Arc::new(ReqwestTransport::from_config((*env.network).clone()));
+    let (url, server) = serve(vec![
+        response("200 OK", &[], "small"),
+        response("200 OK", &[], "much larger than allowed"),
+    ]);
+    let small = request(&env, &url);
+    net::send(&env, small);
+    let large = request(&env, &url);
+    net::send(&env, large);
+    server.join().unwrap();
+
+    assert_eq!(net::get_status_code(&env, small), 200);
+    assert_eq!(net::get_status_code(&env, large), -1);
+    assert_eq!(
+        net::error(&env, large).map(|err| err.kind),
+        Some(NetErrorKind::TooLarge)
+    );
+
+    // the limit holds for any transport
+    env.transport = Arc::new(MockTransport::default());
+    env.network = Arc::new(NetworkConfig {
+        max_body_size: Some(2),
+        ..NetworkConfig::default()
+    });
+    let descriptor = request(&env, "https://example.com/done");
+    net::send(&env, descriptor);
+    assert_eq!(
+        net::error(&env, descriptor).map(|err| err.kind),
+        Some(NetErrorKind::TooLarge)
+    );
+}
+
+#[test]
+pub fn test_download() {
+    let env = &env();
+    let (url, server) = serve(vec![
+        response("302 Found", &["Location: /page.png"], ""),
+        response("200 OK", &["Content-Type: image/png"], "image data"),
+    ]);
+    let descriptor = request(env, &url);
+    let req = env.store().get_request(&descriptor).cloned().unwrap();
+    let mut body = Vec::new();
+    let res = net::download(env, &req, &mut body).unwrap();
+    server.join().unwrap();
+
+    assert_eq!(res.status_code, 200);
+    assert_eq!(res.url, Some(format!("{}/page.png", url)));
+    assert!(res.data.is_empty());
+    assert_eq!(body, b"image data");
+}
+
+// Streams its body in pieces, and won't hand back a whole one.
+#[derive(Default)]
+struct Streaming {
+    downloads: Mutex<usize>,
+}
+
+impl HttpTransport for Streaming {
+    fn send(&self, _request: &Request) -> anyhow::Result<Response> {
+        anyhow::bail!("expected a download")
+    }
+
+    fn download(&self, request: &Request, body: &mut dyn Write) -> anyhow::Result<Response> {
+        *self.downloads.lock().unwrap() += 1;
+        for chunk in ["chunk one, ", "chunk two"] {
+            body.write_all(chunk.as_bytes())?;
+        }
+        Ok(Response {
+            status_code: 200,
+            headers: vec![(String::from("cache-control"), String::from("max-age=60"))],
+            url: request.url.clone(),
+            data: Vec::new(),
+            timings: None,
+        })
+    }
+}
+
+#[test]
+pub fn test_wrapped_download() {
+    let mut env = env();
+    let name = format!("aidoku-download-{}", std::process::id());
+    let dir = std::env::temp_dir().join(&name);
+    let path = std::env::temp_dir().join(format!("{}.json", name));
+    let streaming = Arc::new(Streaming::default());
+    let cache = Arc::new(HttpCache::new(&dir, streaming.clone()).unwrap());
+    let cassette = Arc::new(Cassette::record(&path, cache.clone()).unwrap());
+    let har = Arc::new(HarRecorder::new(cassette.clone()));
+    env.transport = har.clone();
+
+    let descriptor = request(&env, "https://example.com/page.png");
+    let req = env.store().get_request(&descriptor).cloned().unwrap();
+    for _ in 0..2 {
+        let mut body = Vec::new();
+        let res = net::download(&env, &req, &mut body).unwrap();
+        assert_eq!(res.status_code, 200);
+        assert_eq!(body, b"chunk one, chunk two");
+    }
+    // the second one came from the cache
+    assert_eq!(*streaming.downloads.lock().unwrap(), 1);
+    // and every wrapper saw the whole body
+    let interactions = cassette.interactions();
+    assert_eq!(interactions.len(), 2);
+    for interaction in interactions {
+        assert_eq!(
+            interaction.response.body.text.as_deref(),
+            Some("chunk one, chunk two")
+        );
+    }
+    let entries = har.har()["log"]["entries"].clone();
+    assert_eq!(entries.as_array().map(Vec::len), Some(2));
+    assert_eq!(
+        entries[0]["response"]["content"]["text"],
+        "chunk one, chunk two"
+    );
+
+    // the cache spooled the body to disk and kept nothing else
+    let files: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|file| file.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().all(|file| !file.ends_with(".tmp")));
+
+    // the size limit holds as the body streams, whatever the transport
+    cache.clear().unwrap();
+    env.network = Arc::new(NetworkConfig {
+        max_body_size: Some(12),
+        ..NetworkConfig::default()
+    });
+    let mut body = Vec::new();
+    let err = net::download(&env, &req, &mut body).unwrap_err();
+    assert_eq!(err.kind, NetErrorKind::TooLarge);
+    assert_eq!(body, b"chunk one, ");
+
+    _ = std::fs::remove_dir_all(&dir);
+    _ = std::fs::remove_file(&path);
+}
+
+#[test]
+pub fn test_capped_copies() {
+    let mut env = env();
+    let path = std::env::temp_dir().join(format!("aidoku-capped-{}.json", std::process::id()));
+    let cassette = Arc::new(
+        Cassette::record(&path, Arc::new(Streaming::default()))
+            .unwrap()
+            .with_body_limit(Some(5)),
+    );
+    let har = Arc::new(HarRecorder::new(cassette.clone()).with_body_limit(Some(5)));
+    env.transport = har.clone();
+
+    let descriptor = request(&env, "https://example.com/page.png");
+    let req = env.store().get_request(&descriptor).cloned().unwrap();
+    let mut body = Vec::new();
+    net::download(&env, &req, &mut body).unwrap();
+    assert_eq!(body, b"chunk one, chunk two");
+
+    // the wrappers only kept the start of the body
+    let content = har.har()["log"]["entries"][0]["response"]["content"].clone();
+    assert_eq!(content["text"], "chunk");
+    assert_eq!(content["size"], 20);
+    assert_eq!(content["comment"], "truncated from 20 bytes");
+    let recorded = cassette.interactions()[0].response.body.clone();
+    assert_eq!(recorded.base64.as_deref(), Some("Y2h1bms="));
+    assert_eq!(recorded.size, Some(20));
+
+    // and a body that was cut short can't be replayed
+    let replay = Cassette::replay(&path).unwrap();
+    assert!(replay.send(&req).is_err());
+    assert!(replay.take_unmatched().is_empty());
+    _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,69 @@
+use aidoku_runner::wasm::env::WasmEnv;
+use aidoku_runner::wasm::imports::net;
+use aidoku_runner::wasm::rate_limit::{RateLimit, RateLimiter};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+pub fn test_source_limit() {
+    let limiter = RateLimiter::new();
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    limiter.set_limit(2);
+    limiter.set_period(10);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    assert!(limiter.reserve() > Duration::from_secs(9));
+}
+
+#[test]
+pub fn test_queueing() {
+    let limiter = RateLimiter::new();
+    limiter.set_limit(1);
+    limiter.set_period(10);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    let second = limiter.reserve();
+    let third = limiter.reserve();
+    assert!(second > Duration::from_secs(9));
+    assert!(third > Duration::from_secs(19));
+}
+
+#[test]
+pub fn test_shared_between_instances() {
+    let first = WasmEnv::for_source("test.shared");
+    let second = WasmEnv::for_source("test.shared");
+    let other = WasmEnv::for_source("test.other");
+    assert!(Arc::ptr_eq(&first.rate_limiter, &second.rate_limiter));
+    assert!(!Arc::ptr_eq(&first.rate_limiter, &other.rate_limiter));
+
+    net::set_rate_limit(&first, 1);
+    net::set_rate_limit_period(&first, 10);
+    assert_eq!(first.rate_limiter.reserve(), Duration::ZERO);
+    assert!(second.rate_limiter.reserve() > Duration::ZERO);
+    assert_eq!(other.rate_limiter.reserve(), Duration::ZERO);
+}
+
+#[test]
+pub fn test_floor() {
+    let limiter = RateLimiter::new();
+    limiter.set_limit(100);
+    limiter.set_period(1);
+    limiter.set_floor(Some(RateLimit::new(1, Duration::from_secs(10))));
+    assert_eq!(limiter.limits().len(), 2);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    assert!(limiter.reserve() > Duration::from_secs(9));
+}
+
+#[test]
+pub fn test_override() {
+    let limiter = RateLimiter::new();
+    limiter.set_limit(1);
+    limiter.set_period(10);
+    limiter.set_override(Some(RateLimit::new(2, Duration::from_secs(10))));
+    assert_eq!(
+        limiter.limits(),
+        vec![RateLimit::new(2, Duration::from_secs(10))]
+    );
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    assert_eq!(limiter.reserve(), Duration::ZERO);
+    assert!(limiter.reserve() > Duration::ZERO);
+}
@@ -0,0 +1,448 @@
+use aidoku_runner::wasm::env::{WasmEnv, WasmObject};
+use aidoku_runner::wasm::imports::{json, std};
+use indexmap::IndexMap;
+
+mod common;
+use common::env;
+
+fn write(env: &WasmEnv, str: &str) -> (u32, u32) {
+    env.write_string(str, 0);
+    (0, str.len() as u32)
+}
+
+fn store(env: &WasmEnv, obj: WasmObject) -> i32 {
+    env.store().store_value(obj, None)
+}
+
+fn object(env: &WasmEnv, pairs: &[(&str, WasmObject)]) -> i32 {
+    let map: IndexMap<String, WasmObject> = pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    store(env, WasmObject::Object(map))
+}
+
+fn ints(env: &WasmEnv, descriptor: i32) -> Vec<i64> {
+    match env.store().read_value(descriptor).as_deref() {
+        Some(WasmObject::Array(arr)) => arr
+            .iter()
+            .map(|v| match v {
+                WasmObject::Int(int) => *int,
+                _ => -1,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[test]
+pub fn test_copy() {
+    let env = env();
+    let original = store(&env, WasmObject::Array(vec![WasmObject::Int(1)]));
+    let copy = std::copy(&env, original);
+    std::array_append(&env, copy, store(&env, WasmObject::Int(2)));
+    assert_eq!(ints(&env, original), vec![1]);
+    assert_eq!(ints(&env, copy), vec![1, 2]);
+    assert_eq!(std::copy(&env, -1), -1);
+}
+
+#[test]
+pub fn test_destroy() {
+    let env = env();
+    let descriptor = store(&env, WasmObject::Int(1));
+    std::destroy(&env, descriptor);
+    assert!(env.store().read_value(descriptor).is_none());
+}
+
+#[test]
+pub fn test_typeof() {
+    let env = env();
+    assert_eq!(std::value_kind(&env, store(&env, WasmObject::Int(1))), 1);
+    assert_eq!(std::value_kind(&env, store(&env, WasmObject::Date(1.0))), 7);
+    assert_eq!(std::value_kind(&env, -1), 0);
+}
+
+#[test]
+pub fn test_create_null() {
+    let env = env();
+    let descriptor = std::create_null(&env);
+    assert!(matches!(
+        env.store().read_value(descriptor).as_deref(),
+        Some(WasmObject::Null)
+    ));
+}
+
+#[test]
+pub fn test_create_int() {
+    let env = env();
+    let descriptor = std::create_int(&env, 42);
+    assert_eq!(std::read_int(&env, descriptor), 42);
+}
+
+#[test]
+pub fn test_create_float() {
+    let env = env();
+    let descriptor = std::create_float(&env, 1.5);
+    assert_eq!(std::read_float(&env, descriptor), 1.5);
+}
+
+#[test]
+pub fn test_create_bool() {
+    let env = env();
+    assert_eq!(std::read_bool(&env, std::create_bool(&env, 1)), 1);
+    assert_eq!(std::read_bool(&env, std::create_bool(&env, 0)), 0);
+}
+
+#[test]
+pub fn test_create_string() {
+    let env = &env();
+    let (ptr, len) = write(env, "hello");
+    let descriptor = std::create_string(env, ptr, len);
+    assert!(
+        matches!(env.store().read_value(descriptor).as_deref(), Some(WasmObject::String(s)) if s == "hello")
+    );
+}
+
+#[test]
+pub fn test_create_object() {
+    let env = env();
+    let descriptor = std::create_object(&env);
+    assert_eq!(std::object_len(&env, descriptor), 0);
+    assert_eq!(std::value_kind(&env, descriptor), 6);
+}
+
+#[test]
+pub fn test_create_array() {
+    let env = env();
+    let descriptor = std::create_array(&env);
+    assert_eq!(std::array_len(&env, descriptor), 0);
+    assert_eq!(std::value_kind(&env, descriptor), 5);
+}
+
+#[test]
+pub fn test_create_date() {
+    let env = env();
+    assert_eq!(std::read_date(&env, std::create_date(&env, 1000.0)), 1000.0);
+    assert!(std::read_date(&env, std::create_date(&env, -1.0)) > 0.0);
+}
+
+#[test]
+pub fn test_string_len() {
+    let env = env();
+    assert_eq!(
+        std::string_len(&env, store(&env, WasmObject::String("abc".into()))),
+        3
+    );
+    assert_eq!(std::string_len(&env, store(&env, WasmObject::Int(1234))), 4);
+    assert_eq!(std::string_len(&env, store(&env, WasmObject::Null)), 0);
+}
+
+#[test]
+pub fn test_read_string() {
+    let env = &env();
+    let read = |obj: WasmObject| {
+        let descriptor = store(env, obj);
+        let len = std::string_len(env, descriptor);
+        std::read_string(env, descriptor, 0, len);
+        env.read_string(0, len as u32).unwrap()
+    };
+    assert_eq!(read(WasmObject::String("hello".into())), "hello");
+    assert_eq!(read(WasmObject::Int(-12)), "-12");
+    assert_eq!(read(WasmObject::Float(2.0)), "2.0");
+    assert_eq!(read(WasmObject::Bool(true)), "true");
+}
+
+#[test]
+pub fn test_read_int() {
+    let env = env();
+    let read = |obj: WasmObject| std::read_int(&env, store(&env, obj));
+    assert_eq!(read(WasmObject::Int(7)), 7);
+    assert_eq!(read(WasmObject::Float(7.9)), 7);
+    assert_eq!(read(WasmObject::Bool(true)), 1);
+    assert_eq!(read(WasmObject::String(" 12 ".into())), 12);
+    assert_eq!(read(WasmObject::String("12.5".into())), 12);
+    assert_eq!(read(WasmObject::String("abc".into())), -1);
+    assert_eq!(read(WasmObject::Null), -1);
+}
+
+#[test]
+pub fn test_read_float() {
+    let env = env();
+    let read = |obj: WasmObject| std::read_float(&env, store(&env, obj));
+    assert_eq!(read(WasmObject::Float(1.25)), 1.25);
+    assert_eq!(read(WasmObject::Int(3)), 3.0);
+    assert_eq!(read(WasmObject::Bool(false)), 0.0);
+    assert_eq!(read(WasmObject::String("2.5".into())), 2.5);
+    assert_eq!(read(WasmObject::String("abc".into())), -1.0);
+}
+
+#[test]
+pub fn test_read_bool() {
+    let env = env();
+    let read = |obj: WasmObject| std::read_bool(&env, store(&env, obj));
+    assert_eq!(read(WasmObject::Bool(true)), 1);
+    assert_eq!(read(WasmObject::Int(2)), 1);
+    assert_eq!(read(WasmObject::Float(0.0)), 0);
+    assert_eq!(read(WasmObject::String("true".into())), 1);
+    assert_eq!(read(WasmObject::String("no".into())), 0);
+}
+
+#[test]
+pub fn test_read_date() {
+    let env = env();
+    let read = |obj: WasmObject| std::read_date(&env, store(&env, obj));
+    assert_eq!(read(WasmObject::Date(10.0)), 10.0);
+    assert_eq!(read(WasmObject::Float(10.5)), 10.5);
+    assert_eq!(read(WasmObject::Int(10)), 10.0);
+    assert_eq!(read(WasmObject::String("10".into())), -1.0);
+}
+
+#[test]
+pub fn test_read_date_string() {
+    let env = &env();
+    let descriptor = store(env, WasmObject::String("20 oct. 2022 09:00".into()));
+    let (format, locale, timezone) = ("dd MMM yyyy HH:mm", "es_ES", "Asia/Tokyo");
+    env.write_string(format, 0);
+    env.write_string(locale, 32);
+    env.write_string(timezone, 64);
+    let date = std::read_date_string(
+        env,
+        descriptor,
+        0,
+        format.len() as u32,
+        32,
+        locale.len() as u32,
+        64,
+        timezone.len() as u32,
+    );
+    assert_eq!(date, 1666224000.0);
+}
+
+#[test]
+pub fn test_object_len() {
+    let env = env();
+    let descriptor = object(&env, &[("a", WasmObject::Null), ("b", WasmObject::Null)]);
+    assert_eq!(std::object_len(&env, descriptor), 2);
+    assert_eq!(std::object_len(&env, store(&env, WasmObject::Int(1))), 0);
+}
+
+#[test]
+pub fn test_object_get() {
+    let env = &env();
+    let descriptor = object(env, &[("a", WasmObject::Int(1))]);
+    let (ptr, len) = write(env, "a");
+    assert_eq!(
+        std::read_int(env, std::object_get(env, descriptor, ptr, len)),
+        1
+    );
+    let (ptr, len) = write(env, "b");
+    assert_eq!(std::object_get(env, descriptor, ptr, len), -1);
+}
+
+#[test]
+pub fn test_object_set() {
+    let env = &env();
+    let descriptor = object(env, &[]);
+    let (ptr, len) = write(env, "a");
+    std::object_set(env, descriptor, ptr, len, store(env, WasmObject::Int(5)));
+    assert_eq!(
+        std::read_int(env, std::object_get(env, descriptor, ptr, len)),
+        5
+    );
+}
+
+#[test]
+pub fn test_object_remove() {
+    let env = &env();
+    let descriptor = object(env, &[("a", WasmObject::Int(1)), ("b", WasmObject::Int(2))]);
+    let (ptr, len) = write(env, "a");
+    std::object_remove(env, descriptor, ptr, len);
+    assert_eq!(std::object_len(env, descriptor), 1);
+    assert_eq!(std::object_get(env, descriptor, ptr, len), -1);
+}
+
+#[test]
+pub fn test_object_keys() {
+    let env = env();
+    let descriptor = object(
+        &env,
+        &[("a", WasmObject::Int(1)), ("b", WasmObject::Int(2))],
+    );
+    let keys = std::object_keys(&env, descriptor);
+    let mut keys: Vec<String> = match env.store().read_value(keys).as_deref() {
+        Some(WasmObject::Array(arr)) => arr
+            .iter()
+            .filter_map(|key| match key {
+                WasmObject::String(key) => Some(key.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    keys.sort();
+    assert_eq!(keys, vec!["a", "b"]);
+}
+
+#[test]
+pub fn test_object_values() {
+    let env = env();
+    let descriptor = object(
+        &env,
+        &[("a", WasmObject::Int(1)), ("b", WasmObject::Int(2))],
+    );
+    let mut values = ints(&env, std::object_values(&env, descriptor));
+    values.sort();
+    assert_eq!(values, vec![1, 2]);
+}
+
+#[test]
+pub fn test_object_order() {
+    let env = &env();
+    let (ptr, len) = write(env, r#"{"c": 3, "a": 1, "b": 2}"#);
+    let descriptor = json::parse(env, ptr, len);
+    assert_eq!(
+        ints(env, std::object_values(env, descriptor)),
+        vec![3, 1, 2]
+    );
+
+    // new keys go at the end, and removing one leaves the rest where they were
+    let (ptr, len) = write(env, "z");
+    std::object_set(env, descriptor, ptr, len, store(env, WasmObject::Int(26)));
+    let (ptr, len) = write(env, "a");
+    std::object_remove(env, descriptor, ptr, len);
+    assert_eq!(
+        ints(env, std::object_values(env, descriptor)),
+        vec![3, 2, 26]
+    );
+
+    let keys = std::object_keys(env, descriptor);
+    assert!(matches!(
+        env.store().read_value(keys).as_deref(),
+        Some(WasmObject::Array(arr)) if matches!(
+            arr.as_slice(),
+            [WasmObject::String(c), WasmObject::String(b), WasmObject::String(z)]
+                if c == "c" && b == "b" && z == "z"
+        )
+    ));
+}
+
+#[test]
+pub fn test_array_len() {
+    let env = env();
+    let descriptor = store(&env, WasmObject::Array(vec![WasmObject::Null; 3]));
+    assert_eq!(std::array_len(&env, descriptor), 3);
+    assert_eq!(std::array_len(&env, store(&env, WasmObject::Int(1))), 0);
+}
+
+#[test]
+pub fn test_array_get() {
+    let env = env();
+    let descriptor = store(
+        &env,
+        WasmObject::Array(vec![WasmObject::Int(1), WasmObject::Int(2)]),
+    );
+    assert_eq!(std::read_int(&env, std::array_get(&env, descriptor, 1)), 2);
+    assert_eq!(std::array_get(&env, descriptor, 2), -1);
+    assert_eq!(std::array_get(&env, descriptor, -1), -1);
+}
+
+#[test]
+pub fn test_array_set() {
+    let env = env();
+    let descriptor = store(
+        &env,
+        WasmObject::Array(vec![WasmObject::Int(1), WasmObject::Int(2)]),
+    );
+    std::array_set(&env, descriptor, 0, store(&env, WasmObject::Int(3)));
+    std::array_set(&env, descriptor, 5, store(&env, WasmObject::Int(4)));
+    assert_eq!(ints(&env, descriptor), vec![3, 2]);
+}
+
+#[test]
+pub fn test_array_append() {
+    let env = env();
+    let descriptor = store(&env, WasmObject::Array(Vec::new()));
+    std::array_append(&env, descriptor, store(&env, WasmObject::Int(1)));
+    std::array_append(&env, descriptor, store(&env, WasmObject::Int(2)));
+    assert_eq!(ints(&env, descriptor), vec![1, 2]);
+}
+
+#[test]
+pub fn test_array_remove() {
+    let env = env();
+    let descriptor = store(
+        &env,
+        WasmObject::Array(vec![
+            WasmObject::Int(1),
+            WasmObject::Int(2),
+            WasmObject::Int(3),
+        ]),
+    );
+    std::array_remove(&env, descriptor, 1);
+    std::array_remove(&env, descriptor, 7);
+    assert_eq!(ints(&env, descriptor), vec![1, 3]);
+}
+
+#[test]
+pub fn test_array_children() {
+    let env = &env();
+    let parent = object(
+        env,
+        &[(
+            "list",
+            WasmObject::Array(vec![
+                WasmObject::Int(1),
+                WasmObject::Int(2),
+                WasmObject::Int(3),
+            ]),
+        )],
+    );
+    let (ptr, len) = write(env, "list");
+    let list = std::object_get(env, parent, ptr, len);
+    let items: Vec<i32> = (0..3).map(|idx| std::array_get(env, list, idx)).collect();
+
+    // removing an element destroys its descriptor and shifts the later ones
+    std::array_remove(env, list, 1);
+    assert_eq!(std::read_int(env, items[0]), 1);
+    assert!(env.store().read_value(items[1]).is_none());
+    assert_eq!(std::read_int(env, items[2]), 3);
+
+    // replacing one does the same, even through another view of the array
+    let other = std::object_get(env, parent, ptr, len);
+    std::array_set(env, other, 1, store(env, WasmObject::Int(4)));
+    assert!(env.store().read_value(items[2]).is_none());
+    assert_eq!(std::read_int(env, items[0]), 1);
+
+    std::object_remove(env, parent, ptr, len);
+    assert!(env.store().read_value(list).is_none());
+    assert!(env.store().read_value(items[0]).is_none());
+}
+
+#[test]
+pub fn test_object_children() {
+    let env = &env();
+    let mut inner = IndexMap::new();
+    inner.insert(String::from("x"), WasmObject::Int(1));
+    let parent = object(
+        env,
+        &[("a", WasmObject::Object(inner)), ("b", WasmObject::Int(2))],
+    );
+    let (ptr, len) = write(env, "b");
+    let other = std::object_get(env, parent, ptr, len);
+    let (ptr, len) = write(env, "a");
+    let child = std::object_get(env, parent, ptr, len);
+    let (ptr, len) = write(env, "x");
+    let grandchild = std::object_get(env, child, ptr, len);
+    assert_eq!(std::read_int(env, grandchild), 1);
+
+    // replacing a value destroys the descriptors into the old one
+    let (ptr, len) = write(env, "a");
+    std::object_set(env, parent, ptr, len, store(env, WasmObject::Int(5)));
+    assert!(env.store().read_value(child).is_none());
+    assert!(env.store().read_value(grandchild).is_none());
+    assert_eq!(std::read_int(env, other), 2);
+    assert_eq!(
+        std::read_int(env, std::object_get(env, parent, ptr, len)),
+        5
+    );
+}
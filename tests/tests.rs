@@ -1,10 +1,22 @@
-use aidoku_runner::wasm::env::WasmObject;
-use aidoku_runner::wasm::models::{Filter, FilterType};
+use aidoku_runner::wasm::allowlist::Allowlist;
+use aidoku_runner::wasm::cassette::Cassette;
+use aidoku_runner::wasm::env::{
+    HttpMethod, Response, ValueKey, WasmEnv, WasmGlobalStore, WasmObject,
+};
+use aidoku_runner::wasm::imports::net;
+use aidoku_runner::wasm::models::{Filter, FilterType, Manga};
 use aidoku_runner::AidokuSource;
+use indexmap::IndexMap;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasmer::Value;
 
 fn source() -> AidokuSource {
     let bytes = include_bytes!("main.wasm");
-    let source = AidokuSource::new(bytes);
+    let source = AidokuSource::from_bytes(bytes);
     source.initialize();
     source
 }
@@ -35,21 +47,308 @@ pub fn test_implemented() {
         .is_ok());
 }
 
-// #[test]
-// pub fn test_descriptor_count() {
-//     let source = source();
+#[test]
+pub fn test_descriptor_reuse() {
+    let mut store = WasmGlobalStore::new();
 
-//     let std_count = { source.env.store().std_descriptors.len() };
-//     _ = source.get_manga_list(Vec::new(), 1);
-//     assert_eq!(std_count, source.env.store().std_descriptors.len());
+    let first = store.store_value(WasmObject::Int(1), None);
+    store.remove_value(first);
+    let second = store.store_value(WasmObject::Int(2), None);
 
-//     _ = source.get_manga_details(Manga::new(String::from("1")));
-//     assert_eq!(std_count, source.env.store().std_descriptors.len());
-// }
+    assert_ne!(first, second);
+    assert!(store.read_value(first).is_none());
+    assert!(matches!(
+        store.read_value(second).as_deref(),
+        Some(WasmObject::Int(2))
+    ));
+    assert_eq!(store.value_count(), 1);
+
+    store.set_value(first, WasmObject::Int(3));
+    assert!(matches!(
+        store.read_value(second).as_deref(),
+        Some(WasmObject::Int(2))
+    ));
+}
 
 #[test]
-pub fn test_manga_list() {
+pub fn test_set_unknown_value() {
+    let mut store = WasmGlobalStore::new();
+
+    store.set_value(5, WasmObject::Int(1));
+    assert!(store.read_value(5).is_none());
+    assert_eq!(store.value_count(), 0);
+
+    let descriptor = store.store_value(WasmObject::Int(1), None);
+    store.remove_value(descriptor);
+    store.set_value(descriptor, WasmObject::Int(2));
+    assert!(store.read_value(descriptor).is_none());
+    assert_eq!(store.value_count(), 0);
+}
+
+#[test]
+pub fn test_request_reuse() {
+    let mut store = WasmGlobalStore::new();
+
+    let first = store.new_request(HttpMethod::Get);
+    store.remove_request(first);
+    let second = store.new_request(HttpMethod::Post);
+
+    assert_ne!(first, second);
+    assert!(store.get_request(&first).is_none());
+    assert_eq!(store.get_request(&second).unwrap().method, HttpMethod::Post);
+    assert_eq!(store.request_count(), 1);
+}
+
+#[test]
+pub fn test_descriptor_churn() {
+    let mut store = WasmGlobalStore::new();
+
+    for i in 0..100_000 {
+        let descriptor = store.store_value(WasmObject::Int(i), None);
+        assert!(descriptor >= 0);
+        store.remove_value(descriptor);
+    }
+    assert_eq!(store.value_count(), 0);
+}
+
+#[test]
+pub fn test_child_descriptors() {
+    let mut store = WasmGlobalStore::new();
+
+    let mut map = IndexMap::new();
+    map.insert(
+        String::from("list"),
+        WasmObject::Array(vec![WasmObject::Int(1), WasmObject::Int(2)]),
+    );
+    let parent = store.store_value(WasmObject::Object(map), None);
+    let list = store.store_child(parent, ValueKey::Key(String::from("list")));
+    let item = store.store_child(list, ValueKey::Index(1));
+    assert_eq!(
+        store.store_child(parent, ValueKey::Key(String::from("x"))),
+        -1
+    );
+
+    // mutations through the parent are visible to the child
+    if let Some(WasmObject::Object(map)) = store.value_mut(parent) {
+        map.insert(
+            String::from("list"),
+            WasmObject::Array(vec![WasmObject::Int(3); 2]),
+        );
+    }
+    assert!(matches!(
+        store.read_value(item).as_deref(),
+        Some(WasmObject::Int(3))
+    ));
+
+    // and the other way around
+    store.set_value(item, WasmObject::Int(4));
+    if let Some(WasmObject::Object(map)) = store.read_value(parent).as_deref() {
+        assert!(
+            matches!(&map["list"], WasmObject::Array(arr) if matches!(arr[1], WasmObject::Int(4)))
+        );
+    }
+
+    store.remove_value(parent);
+    assert!(store.read_value(list).is_none());
+    assert!(store.read_value(item).is_none());
+    assert_eq!(store.value_count(), 0);
+}
+
+#[test]
+pub fn test_call_arena() {
+    let source = AidokuSource::from_bytes(
+        br#"(module
+            (import "std" "create_int" (func $create_int (param i64) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "get_manga_details") (param i32) (result i32)
+                (drop (call $create_int (i64.const 1)))
+                (drop (call $create_int (i64.const 2)))
+                (call $create_int (i64.const 3))))"#,
+    );
+
+    assert!(source
+        .get_manga_details(Manga::new(String::from("1")))
+        .is_none());
+    let store = source.env.store();
+    assert_eq!(store.value_count(), 0);
+    assert_eq!(store.reclaimed_count(), 2);
+}
+
+#[test]
+pub fn test_initialize_arena() {
+    let source = AidokuSource::from_bytes(
+        br#"(module
+            (import "std" "create_int" (func $create_int (param i64) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "initialize")
+                (drop (call $create_int (i64.const 1)))))"#,
+    );
+    source.set_leak_check(true);
+
+    source.initialize();
+    let leaks = source.take_leaks();
+    assert_eq!(leaks.len(), 1);
+    assert_eq!(leaks[0].call, "initialize");
+    assert_eq!(source.env.store().value_count(), 0);
+}
+
+#[test]
+pub fn test_leak_check() {
+    let source = AidokuSource::from_bytes(
+        br#"(module
+            (import "std" "create_int" (func $create_int (param i64) (result i32)))
+            (import "net" "init" (func $init (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (func (export "get_manga_details") (param i32) (result i32)
+                (drop (call $create_int (i64.const 1)))
+                (drop (call $init (i32.const 0)))
+                (i32.const -1)))"#,
+    );
+    source.set_leak_check(true);
+
+    _ = source.get_manga_details(Manga::new(String::from("1")));
+    let leaks = source.take_leaks();
+    assert_eq!(leaks.len(), 1);
+    assert_eq!(leaks[0].call, "get_manga_details");
+    assert_eq!(leaks[0].values.len(), 1);
+    assert_eq!(leaks[0].values[0].kind, "int");
+    assert_eq!(leaks[0].values[0].import, Some("std.create_int"));
+    assert_eq!(leaks[0].requests.len(), 1);
+    assert_eq!(leaks[0].requests[0].import, Some("net.init"));
+}
+
+#[test]
+pub fn test_descriptor_count() {
     let source = source();
+    source.set_leak_check(true);
+
+    let std_count = { source.env.store().value_count() };
+    _ = source.get_manga_list(Vec::new(), 1);
+    assert_eq!(std_count, source.env.store().value_count());
+
+    _ = source.get_manga_details(Manga::new(String::from("1")));
+    assert_eq!(std_count, source.env.store().value_count());
+    // the sample source cleans up after itself
+    let leaks = source.take_leaks();
+    assert!(leaks.is_empty(), "{}", leaks[0]);
+}
+
+#[test]
+pub fn test_send_releases_store() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let (unblock, blocked) = mpsc::channel::<()>();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        _ = stream.read(&mut buf);
+        // hold the response until the test has touched the store
+        _ = blocked.recv_timeout(Duration::from_secs(5));
+        _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+    });
+
+    let mut env = WasmEnv::new();
+    env.allowlist = Arc::new(Allowlist::new().allow("127.0.0.1"));
+    let descriptor = {
+        let mut store = env.store();
+        let descriptor = store.new_request(HttpMethod::Get);
+        let mut req = store.get_request(&descriptor).unwrap().clone();
+        req.url = Some(url);
+        store.set_request(descriptor, req);
+        descriptor
+    };
+    let sender = {
+        let env = env.clone();
+        thread::spawn(move || net::send(&env, descriptor))
+    };
+
+    let start = Instant::now();
+    let mut locked = false;
+    while start.elapsed() < Duration::from_secs(2) {
+        if env.store.try_lock().is_ok() {
+            locked = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    // give the request time to be in flight before checking again
+    thread::sleep(Duration::from_millis(100));
+    locked &= env.store.try_lock().is_ok();
+    _ = unblock.send(());
+    sender.join().unwrap();
+    server.join().unwrap();
+
+    assert!(locked);
+    let store = env.store();
+    let res = store
+        .get_request(&descriptor)
+        .unwrap()
+        .response
+        .clone()
+        .unwrap();
+    assert_eq!(res.status_code, 200);
+    assert_eq!(res.data, b"ok");
+}
+
+#[test]
+pub fn test_parse_releases_store() {
+    // big enough that parsing it takes a while
+    let item = r#"{"title":"manga","tags":["a","b"],"chapters":[1,2,3]}"#;
+    let body = format!("[{}]", vec![item; 100_000].join(","));
+    let env = WasmEnv::new();
+    let descriptor = {
+        let mut store = env.store();
+        let descriptor = store.new_request(HttpMethod::Get);
+        let mut req = store.get_request(&descriptor).unwrap().clone();
+        req.response = Some(Response {
+            status_code: 200,
+            headers: Vec::new(),
+            url: None,
+            data: body.into_bytes(),
+            timings: None,
+        });
+        store.set_request(descriptor, req);
+        descriptor
+    };
+    let parser = {
+        let env = env.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            (net::json(&env, descriptor), start.elapsed())
+        })
+    };
+
+    // other imports only wait for the body to be copied out, not parsed
+    let mut longest = Duration::ZERO;
+    while !parser.is_finished() {
+        let start = Instant::now();
+        drop(env.store());
+        longest = longest.max(start.elapsed());
+        thread::sleep(Duration::from_millis(1));
+    }
+    let (json, elapsed) = parser.join().unwrap();
+    assert!(json >= 0);
+    assert!(
+        longest < elapsed / 2,
+        "store held for {:?} of {:?}",
+        longest,
+        elapsed
+    );
+}
+
+#[test]
+pub fn test_manga_list() {
+    let cassette = Arc::new(
+        Cassette::from_env(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/cassettes/manga_list.json"
+        ))
+        .unwrap(),
+    );
+    let mut env = WasmEnv::new();
+    env.transport = cassette.clone();
+    let source = AidokuSource::new_with_env(include_bytes!("main.wasm"), env);
+    source.initialize();
 
     let filters = vec![Filter {
         kind: FilterType::Title,
@@ -66,6 +365,101 @@ pub fn test_manga_list() {
             .join(", ");
         println!("manga: {}", titles);
     }
+    assert!(cassette.unmatched().is_empty());
+}
+
+// Fetches the url at `url` into `out` through the net imports, returning the
+// body's size, or -1 unless the response is a 200.
+const FETCH_SOURCE: &[u8] = br#"(module
+    (import "net" "init" (func $init (param i32) (result i32)))
+    (import "net" "set_url" (func $set_url (param i32 i32 i32)))
+    (import "net" "send" (func $send (param i32)))
+    (import "net" "get_status_code" (func $status (param i32) (result i32)))
+    (import "net" "get_data_size" (func $size (param i32) (result i32)))
+    (import "net" "get_data" (func $data (param i32 i32 i32)))
+    (import "net" "close" (func $close (param i32)))
+    (memory (export "memory") 1)
+    (func (export "fetch") (param $url i32) (param $len i32) (param $out i32) (result i32)
+        (local $req i32)
+        (local $size i32)
+        (local.set $req (call $init (i32.const 0)))
+        (call $set_url (local.get $req) (local.get $url) (local.get $len))
+        (call $send (local.get $req))
+        (local.set $size (i32.const -1))
+        (if (i32.eq (call $status (local.get $req)) (i32.const 200))
+            (then
+                (local.set $size (call $size (local.get $req)))
+                (call $data (local.get $req) (local.get $out) (local.get $size))))
+        (call $close (local.get $req))
+        (local.get $size)))"#;
+
+fn fetch_source(cassette: &str) -> (AidokuSource, Arc<Cassette>) {
+    let cassette = Arc::new(
+        Cassette::from_env(format!(
+            "{}/tests/cassettes/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            cassette
+        ))
+        .unwrap(),
+    );
+    let mut env = WasmEnv::new();
+    env.transport = cassette.clone();
+    (AidokuSource::new_with_env(FETCH_SOURCE, env), cassette)
+}
+
+// Has the source fetch `url`, returning its body.
+fn fetch(source: &AidokuSource, url: &str) -> Option<String> {
+    let memory = source.instance.exports.get_memory("memory").unwrap();
+    for (cell, byte) in memory.view::<u8>().iter().zip(url.bytes()) {
+        cell.set(byte);
+    }
+    let out = 1024;
+    let size = source
+        .instance
+        .exports
+        .get_function("fetch")
+        .unwrap()
+        .call(&[Value::I32(0), Value::I32(url.len() as i32), Value::I32(out)])
+        .unwrap()[0]
+        .unwrap_i32();
+    if size < 0 {
+        return None;
+    }
+    let bytes: Vec<u8> = memory.view::<u8>()[out as usize..(out + size) as usize]
+        .iter()
+        .map(|cell| cell.get())
+        .collect();
+    Some(String::from_utf8(bytes).unwrap())
+}
+
+#[test]
+pub fn test_cassette_replay() {
+    let (source, cassette) = fetch_source("fetch.json");
+    assert_eq!(
+        fetch(&source, "https://example.com/manga/1").as_deref(),
+        Some("One Piece")
+    );
+    assert_eq!(
+        fetch(&source, "https://example.com/manga/2").as_deref(),
+        Some("Naruto")
+    );
+
+    // a request that wasn't recorded fails instead of reaching the network
+    assert_eq!(fetch(&source, "https://example.com/manga/3"), None);
+    assert_eq!(
+        cassette.take_unmatched(),
+        vec![String::from("GET https://example.com/manga/3")]
+    );
+}
+
+#[test]
+#[should_panic(expected = "no recorded response for GET https://example.com/manga/3")]
+pub fn test_cassette_unmatched() {
+    let (source, cassette) = fetch_source("fetch.json");
+    assert_eq!(fetch(&source, "https://example.com/manga/3"), None);
+    // dropping the last reference fails the test
+    drop(source);
+    drop(cassette);
 }
 
 // use std::io;
@@ -76,7 +470,7 @@ pub fn test_manga_list() {
 //     println!("Aidoku CLI");
 
 //     let bytes = include_bytes!("../main.wasm");
-//     let source = AidokuSource::new(bytes);
+//     let source = AidokuSource::from_bytes(bytes);
 //     source.initialize();
 
 //     println!("Loaded Source: en.test");